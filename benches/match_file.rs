@@ -0,0 +1,89 @@
+//! Times localizing a large playlist against a synthetic music index.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::hint::black_box;
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use playlist_localizer::{
+    m3u_playlist, DefaultMatcher, Entry, MatchOptions, MusicIndex, StemOptions,
+};
+
+/// An index of 20 songs on each of 10 albums of 100 artists, the songs don't have to exist.
+fn synthetic_index(options: &MatchOptions) -> MusicIndex {
+    let mut songs: HashMap<OsString, Vec<PathBuf>> = HashMap::new();
+    for artist in 0..100 {
+        for album in 0..10 {
+            for song in 0..20 {
+                let path = PathBuf::from(format!(
+                    "/music/Artist {artist}/Album {album}/{song:02} - Song {}.mp3",
+                    (artist * 7 + song) % 500
+                ));
+                let key = options.stem.stem_key(path.file_stem().unwrap());
+                songs.entry(key).or_default().push(path);
+            }
+        }
+    }
+    songs.values_mut().for_each(|s| s.sort());
+    MusicIndex {
+        songs,
+        playlists: Vec::new(),
+        roots: vec![PathBuf::from("/music")],
+    }
+}
+
+/// 5000 entries from another machine, some of which have another extension or aren't in the
+/// index at all.
+fn playlist_entries() -> Vec<Entry> {
+    (0..5000)
+        .map(|i| {
+            let (artist, album, song) = ((i * 13) % 120, (i / 7) % 10, i % 20);
+            let extension = if i % 3 == 0 { "flac" } else { "mp3" };
+            let path = format!(
+                "/home/someone/Music/Artist {artist}/Album {album}/{song:02} - Song {}.{extension}",
+                (artist * 7 + song) % 500
+            );
+            Entry {
+                path: PathBuf::from(path),
+                extinf: None,
+                comments: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+fn localize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("localize 5000 entries");
+    let entries = playlist_entries();
+    let default = MatchOptions::default();
+    let ignore_case = MatchOptions {
+        stem: StemOptions {
+            ignore_case: true,
+            fold_accents: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    for (name, options) in [("default", &default), ("ignore case", &ignore_case)] {
+        let index = synthetic_index(options);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                m3u_playlist(
+                    &DefaultMatcher,
+                    &index,
+                    None,
+                    None,
+                    options,
+                    black_box(&entries),
+                    None,
+                    String::new(),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, localize);
+criterion_main!(benches);
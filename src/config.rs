@@ -0,0 +1,113 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use directories::ProjectDirs;
+use serde::Deserialize;
+
+use crate::error::AppError;
+
+/// Defaults for some arguments, which are overridden by the ones passed on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub music_dir: Vec<String>,
+    pub output_dir: Option<String>,
+    pub format: Option<String>,
+    pub extension: Option<String>,
+}
+
+impl Config {
+    /// Loads the config passed with `--config`, or the `config.toml` inside the platform's config
+    /// directory. Only a config passed with `--config` has to exist.
+    pub fn load(args: &[OsString]) -> Result<Self, AppError> {
+        let (path, required) = match config_arg(args) {
+            Some(p) => (p, true),
+            None => match ProjectDirs::from("", "", "playlist-localizer") {
+                Some(dirs) => (dirs.config_dir().join("config.toml"), false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(config_error(&path, e.to_string())),
+        };
+        toml::from_str(&contents).map_err(|e| config_error(&path, e.to_string()))
+    }
+}
+
+/// Defaults for a player or device, which are overridden by the arguments passed on the command
+/// line, but take precedence over the config.
+#[derive(Debug)]
+pub struct Profile {
+    pub name: &'static str,
+    pub format: &'static str,
+    pub line_ending: &'static str,
+    /// Write song paths relative to the output directory, so the songs can be copied along with
+    /// the playlists. A base path on the device can be added with `--prepend-prefix`.
+    pub relative: bool,
+}
+
+pub const PROFILES: [Profile; 3] = [
+    Profile {
+        name: "android",
+        format: "extm3u",
+        line_ending: "lf",
+        relative: true,
+    },
+    Profile {
+        name: "foobar",
+        format: "extm3u",
+        line_ending: "crlf",
+        relative: false,
+    },
+    Profile {
+        name: "vlc",
+        format: "xspf",
+        line_ending: "lf",
+        relative: false,
+    },
+];
+
+impl Profile {
+    /// The profile passed with `--profile`, which is needed before the other arguments are
+    /// parsed. Unknown names are rejected when the arguments are parsed.
+    pub fn from_args(args: &[OsString]) -> Option<&'static Profile> {
+        let name = arg_value(args, "--profile")?;
+        PROFILES.iter().find(|p| name == p.name)
+    }
+}
+
+/// The value of `--config`, which is needed before the other arguments are parsed.
+fn config_arg(args: &[OsString]) -> Option<PathBuf> {
+    arg_value(args, "--config").map(PathBuf::from)
+}
+
+/// The value of the argument `long`, passed like `--long value` or `--long=value`.
+fn arg_value(args: &[OsString], long: &str) -> Option<OsString> {
+    let mut args = args.iter();
+    while let Some(a) = args.next() {
+        if a == long {
+            return args.next().cloned();
+        }
+        let value = a
+            .to_str()
+            .and_then(|a| a.strip_prefix(long)?.strip_prefix('='));
+        if let Some(v) = value {
+            return Some(OsString::from(v));
+        }
+    }
+    None
+}
+
+fn config_error(path: &Path, message: String) -> AppError {
+    AppError::Config {
+        path: path.to_path_buf(),
+        message,
+    }
+}
@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+use playlist_localizer::normalize_path;
+use playlist_localizer::parse_entries;
+use playlist_localizer::playlist::{FormatOptions, Playlist, PlaylistFormat};
+
+use crate::playlist_outputs;
+
+/// Compares the songs of `playlists` to the playlists with the same file name previously written
+/// to `dir` and prints the songs that were added, removed or resolved to a different path. Each
+/// playlist is compared to the first of its `outputs` that can be read back, with the song paths
+/// written like the `options` would write them.
+pub fn print_diff(
+    dir: &Path,
+    playlists: &[Playlist],
+    outputs: &[(PlaylistFormat, &str)],
+    options: &FormatOptions,
+) {
+    for p in playlists {
+        let readable = playlist_outputs(p, outputs)
+            .into_iter()
+            .find_map(|(f, e)| Some((f.input_format()?, e)));
+        let Some((input_format, extension)) = readable else {
+            log::debug!(
+                "playlist \"{}\": not compared, its formats can't be read back",
+                p.name()
+            );
+            continue;
+        };
+        let previous_path = p.file_path(dir, extension);
+        if !previous_path.exists() {
+            println!("playlist \"{}\": new", p.name());
+            continue;
+        }
+
+        let previous: Vec<PathBuf> = parse_entries(&previous_path, input_format)
+            .into_iter()
+            .filter(|e| !e.is_trailer())
+            .map(|e| e.path)
+            .collect();
+        let current: Vec<PathBuf> = match p.output_paths_in(dir, options) {
+            Ok(paths) => paths.iter().map(|s| normalize_path(s)).collect(),
+            Err(e) => {
+                log::warn!("playlist \"{}\": not compared: {e}", p.name());
+                continue;
+            }
+        };
+
+        let mut removed: Vec<&PathBuf> = previous.iter().filter(|s| !current.contains(s)).collect();
+        let mut added: Vec<&PathBuf> = current.iter().filter(|s| !previous.contains(s)).collect();
+
+        // songs with the same file stem are considered the same song resolved to a different path
+        let mut changed = Vec::new();
+        removed.retain(|r| {
+            let Some(i) = added.iter().position(|a| a.file_stem() == r.file_stem()) else {
+                return true;
+            };
+            changed.push((*r, added.remove(i)));
+            false
+        });
+
+        if added.is_empty() && removed.is_empty() && changed.is_empty() {
+            continue;
+        }
+
+        println!("playlist \"{}\":", p.name());
+        for s in added {
+            println!("  + {}", s.display());
+        }
+        for s in removed {
+            println!("  - {}", s.display());
+        }
+        for (old, new) in changed {
+            println!("  ~ {} -> {}", old.display(), new.display());
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// The errors that stop a run of the binary.
+#[derive(Debug)]
+pub enum AppError {
+    InvalidMusicDir(io::Error),
+    Config {
+        path: PathBuf,
+        message: String,
+    },
+    Stdin(io::Error),
+    Glob(globset::Error),
+    InvalidOutputDir {
+        path: PathBuf,
+        source: io::Error,
+    },
+    PlaylistZip {
+        path: PathBuf,
+        source: zip::result::ZipError,
+    },
+    ItunesXml {
+        path: PathBuf,
+        source: plist::Error,
+    },
+    RekordboxXml {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Journal {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Report {
+        path: PathBuf,
+        source: io::Error,
+    },
+    EmptyPlaylist {
+        name: String,
+    },
+    /// `--strict` was passed and the output dir is inside a music or playlist dir.
+    OutputInIndexedDir {
+        output_dir: PathBuf,
+        indexed_dir: PathBuf,
+    },
+    /// More files would be overwritten than the confirmation threshold allows.
+    Overwrite {
+        count: usize,
+        dir: PathBuf,
+    },
+    Aborted,
+    /// `--stdout` was passed but not exactly one playlist was found.
+    Stdout {
+        count: usize,
+    },
+    /// Some songs couldn't be copied or linked, the reasons were already reported.
+    Copy {
+        failed: usize,
+    },
+    /// Some playlists couldn't be written, the reasons were already reported.
+    Write {
+        failed: usize,
+    },
+    /// `--strict` was passed and some songs weren't found.
+    Unmatched,
+    /// More output file extensions were passed than formats.
+    ExtensionCount {
+        extensions: usize,
+        formats: usize,
+    },
+    /// `--self-test` was passed and some written song paths don't exist, they were already
+    /// reported.
+    SelfTest {
+        missing: usize,
+    },
+    Watch(notify::Error),
+    Interrupt(ctrlc::Error),
+}
+
+impl AppError {
+    /// `2` if songs weren't found, `1` for everything else.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AppError::Unmatched => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::InvalidMusicDir(e) => write!(f, "Not a valid music dir path: {e}"),
+            AppError::Config { path, message } => {
+                write!(f, "Couldn't read config: {}\n{message}", path.display())
+            }
+            AppError::Stdin(e) => write!(f, "Couldn't read the playlist from stdin: {e}"),
+            AppError::Glob(e) => write!(f, "Invalid glob: {e}"),
+            AppError::InvalidOutputDir { path, source } => {
+                write!(
+                    f,
+                    "Couldn't create output dir: {}\n{:?}",
+                    path.display(),
+                    source
+                )
+            }
+            AppError::PlaylistZip { path, source } => {
+                write!(
+                    f,
+                    "Couldn't read playlist zip: {}\n{:?}",
+                    path.display(),
+                    source
+                )
+            }
+            AppError::ItunesXml { path, source } => {
+                write!(
+                    f,
+                    "Couldn't read iTunes library: {}\n{:?}",
+                    path.display(),
+                    source
+                )
+            }
+            AppError::RekordboxXml { path, source } => {
+                write!(
+                    f,
+                    "Couldn't read Rekordbox collection: {}\n{:?}",
+                    path.display(),
+                    source
+                )
+            }
+            AppError::Journal { path, source } => {
+                write!(f, "Couldn't open journal: {}\n{:?}", path.display(), source)
+            }
+            AppError::Report { path, source } => {
+                write!(f, "Couldn't write report: {}\n{:?}", path.display(), source)
+            }
+            AppError::EmptyPlaylist { name } => write!(f, "Playlist \"{name}\" contains no songs"),
+            AppError::OutputInIndexedDir {
+                output_dir,
+                indexed_dir,
+            } => write!(
+                f,
+                "The output dir {} is inside the indexed dir {}, so the written files would be indexed by the next run",
+                output_dir.display(),
+                indexed_dir.display()
+            ),
+            AppError::Overwrite { count, dir } => write!(
+                f,
+                "{count} existing files in {} would be overwritten, pass --yes to continue",
+                dir.display()
+            ),
+            AppError::Aborted => write!(f, "aborted"),
+            AppError::Stdout { count } => write!(
+                f,
+                "--stdout requires exactly one playlist, but {count} were found, narrow down the playlists to write"
+            ),
+            AppError::Copy { failed } => write!(f, "{failed} songs couldn't be exported"),
+            AppError::Write { failed } => write!(f, "{failed} playlists couldn't be written"),
+            AppError::Unmatched => write!(f, "Some songs weren't found"),
+            AppError::ExtensionCount {
+                extensions,
+                formats,
+            } => write!(
+                f,
+                "{extensions} output file extensions were passed for {formats} formats, pass at most one extension per format"
+            ),
+            AppError::SelfTest { missing } => write!(
+                f,
+                "{missing} song paths in the written playlists don't exist"
+            ),
+            AppError::Watch(e) => write!(f, "Couldn't watch the playlists: {e}"),
+            AppError::Interrupt(e) => write!(f, "Couldn't handle ctrl-c: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::InvalidMusicDir(source)
+            | AppError::Stdin(source)
+            | AppError::InvalidOutputDir { source, .. }
+            | AppError::RekordboxXml { source, .. }
+            | AppError::Journal { source, .. }
+            | AppError::Report { source, .. } => Some(source),
+            AppError::PlaylistZip { source, .. } => Some(source),
+            AppError::Glob(source) => Some(source),
+            AppError::ItunesXml { source, .. } => Some(source),
+            AppError::Watch(source) => Some(source),
+            AppError::Interrupt(source) => Some(source),
+            _ => None,
+        }
+    }
+}
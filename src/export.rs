@@ -0,0 +1,149 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The files the songs of the written playlists are copied to, so the output directory is
+/// self-contained.
+#[derive(Debug)]
+pub struct Export {
+    dir: PathBuf,
+    /// The destination of each song by its source path.
+    destinations: HashMap<PathBuf, PathBuf>,
+    /// The file names which are already used by a song.
+    names: HashSet<OsString>,
+}
+
+/// What happened when exporting a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exported {
+    Written,
+    /// An identical file already exists at the destination.
+    Unchanged,
+}
+
+impl Export {
+    /// Creates an export into the absolute directory `dir`.
+    pub fn new(dir: PathBuf) -> Self {
+        Export {
+            dir,
+            destinations: HashMap::new(),
+            names: HashSet::new(),
+        }
+    }
+
+    /// Assigns a destination to the song, if it doesn't have one yet. Songs with the same file
+    /// name but a different source path get a counter appended to their file stem.
+    pub fn add(&mut self, song: &Path) {
+        if self.destinations.contains_key(song) {
+            return;
+        }
+        let Some(file_name) = song.file_name() else {
+            return;
+        };
+
+        let mut name = file_name.to_os_string();
+        let mut counter = 1;
+        while self.names.contains(&name) {
+            counter += 1;
+            name = song.file_stem().unwrap_or_default().to_os_string();
+            name.push(format!(" ({counter})"));
+            if let Some(ext) = song.extension() {
+                name.push(".");
+                name.push(ext);
+            }
+        }
+
+        self.destinations
+            .insert(song.to_path_buf(), self.dir.join(&name));
+        self.names.insert(name);
+    }
+
+    /// The destination of the song, or the song itself if it wasn't added.
+    pub fn destination<'a>(&'a self, song: &'a Path) -> &'a Path {
+        self.destinations.get(song).map_or(song, PathBuf::as_path)
+    }
+
+    /// The source and destination paths of all added songs.
+    pub fn files(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.destinations
+            .iter()
+            .map(|(s, d)| (s.as_path(), d.as_path()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.destinations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.destinations.is_empty()
+    }
+}
+
+/// Copies `source` to `dest`, unless a file with the same size and modification time already
+/// exists there. The modification time of the copy is set to the one of the source.
+pub fn copy_file(source: &Path, dest: &Path) -> io::Result<Exported> {
+    let source_meta = fs::metadata(source)?;
+    if let Ok(dest_meta) = fs::symlink_metadata(dest) {
+        let same_size = dest_meta.len() == source_meta.len();
+        let same_mtime = dest_meta.modified().ok() == source_meta.modified().ok();
+        if dest_meta.is_file() && same_size && same_mtime {
+            return Ok(Exported::Unchanged);
+        }
+        // don't write through a link of a previous --symlink-files run into the original
+        if dest_meta.is_symlink() {
+            fs::remove_file(dest)?;
+        }
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(source, dest)?;
+    let file = fs::File::options().write(true).open(dest)?;
+    file.set_modified(source_meta.modified()?)?;
+
+    Ok(Exported::Written)
+}
+
+/// Creates a symlink at `dest` pointing to `source`, replacing any other file there. On windows
+/// a hardlink is created instead if creating symlinks isn't permitted.
+pub fn link_file(source: &Path, dest: &Path) -> io::Result<Exported> {
+    if fs::read_link(dest).is_ok_and(|target| target == source) {
+        return Ok(Exported::Unchanged);
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::symlink_metadata(dest).is_ok() {
+        fs::remove_file(dest)?;
+    }
+    symlink(source, dest)?;
+
+    Ok(Exported::Written)
+}
+
+#[cfg(unix)]
+fn symlink(source: &Path, dest: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, dest)
+}
+
+#[cfg(windows)]
+fn symlink(source: &Path, dest: &Path) -> io::Result<()> {
+    // ERROR_PRIVILEGE_NOT_HELD, symlinks require developer mode or admin rights
+    const PRIVILEGE_NOT_HELD: i32 = 1314;
+
+    match std::os::windows::fs::symlink_file(source, dest) {
+        Err(e) if e.raw_os_error() == Some(PRIVILEGE_NOT_HELD) => {
+            fs::hard_link(source, dest).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("missing the privilege to create symlinks and a hardlink couldn't be created either: {e}"),
+                )
+            })
+        }
+        r => r,
+    }
+}
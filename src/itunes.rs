@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+
+use crate::{platform_path, Entry, ExtInf};
+
+#[derive(Deserialize)]
+struct Library {
+    #[serde(rename = "Tracks", default)]
+    tracks: HashMap<String, Track>,
+    #[serde(rename = "Playlists", default)]
+    playlists: Vec<LibraryPlaylist>,
+}
+
+#[derive(Deserialize)]
+struct Track {
+    #[serde(rename = "Location")]
+    location: Option<String>,
+    #[serde(rename = "Name")]
+    name: Option<String>,
+    #[serde(rename = "Artist")]
+    artist: Option<String>,
+    /// The duration in milliseconds.
+    #[serde(rename = "Total Time")]
+    total_time: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct LibraryPlaylist {
+    #[serde(rename = "Name")]
+    name: String,
+    /// The playlist of the whole library.
+    #[serde(rename = "Master", default)]
+    master: bool,
+    #[serde(rename = "Folder", default)]
+    folder: bool,
+    #[serde(rename = "Playlist Items", default)]
+    items: Vec<PlaylistItem>,
+}
+
+#[derive(Deserialize)]
+struct PlaylistItem {
+    #[serde(rename = "Track ID")]
+    track_id: i64,
+}
+
+/// Reads the names and song paths of all playlists of an iTunes or Music `Library.xml` at `path`.
+/// The library itself and playlist folders are skipped.
+pub fn itunes_playlists(path: &Path) -> Result<Vec<(String, Vec<Entry>)>, plist::Error> {
+    let library: Library = plist::from_file(path)?;
+
+    let playlists = library
+        .playlists
+        .into_iter()
+        .filter(|p| !p.master && !p.folder)
+        .map(|p| {
+            let entries = p
+                .items
+                .iter()
+                .filter_map(|i| library.tracks.get(&i.track_id.to_string()))
+                .filter_map(track_entry)
+                .collect();
+            (p.name, entries)
+        })
+        .collect();
+
+    Ok(playlists)
+}
+
+fn track_entry(track: &Track) -> Option<Entry> {
+    let path = file_url_path(track.location.as_deref()?)?;
+    let extinf = ExtInf {
+        duration: track.total_time.map(|t| t / 1000),
+        artist: track.artist.clone(),
+        title: track.name.clone(),
+    };
+
+    Some(Entry {
+        path: platform_path(&path),
+        extinf: Some(extinf),
+        comments: Vec::new(),
+    })
+}
+
+/// Decodes the path of a `file://` URL, like `file:///Users/me/Music/My%20Song.mp3` or
+/// `file://localhost/C:/Music/Song.mp3`.
+pub(crate) fn file_url_path(url: &str) -> Option<String> {
+    let path = url.get(.."file://".len())?;
+    if !path.eq_ignore_ascii_case("file://") {
+        return None;
+    }
+    let path = &url["file://".len()..];
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+
+    // the drive letter of windows paths follows the slash of the URL
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => Some(path[1..].to_string()),
+        _ => Some(path.into_owned()),
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Records the playlists that were completely written, one path per line, so an interrupted run
+/// can be resumed.
+pub struct Journal {
+    file: File,
+    completed: HashSet<PathBuf>,
+}
+
+impl Journal {
+    /// Opens the journal at `path`. When `resume` is set, previously recorded playlists are kept
+    /// and reported as completed, otherwise the journal is cleared.
+    pub fn open(path: &Path, resume: bool) -> io::Result<Self> {
+        let mut completed = HashSet::new();
+        if resume {
+            match std::fs::read_to_string(path) {
+                Ok(contents) => completed.extend(contents.lines().map(PathBuf::from)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(resume)
+            .write(true)
+            .truncate(!resume)
+            .open(path)?;
+
+        Ok(Self { file, completed })
+    }
+
+    pub fn is_completed(&self, playlist_path: &Path) -> bool {
+        self.completed.contains(playlist_path)
+    }
+
+    /// Appends `playlist_path` to the journal and flushes it immediately.
+    pub fn record(&mut self, playlist_path: &Path) -> io::Result<()> {
+        writeln!(self.file, "{}", playlist_path.display())?;
+        self.file.flush()?;
+        self.completed.insert(playlist_path.to_path_buf());
+        Ok(())
+    }
+}
@@ -0,0 +1,2705 @@
+//! Localizes playlists by mapping their song paths to the songs of a local music library.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! let index = playlist_localizer::build_index(Path::new("/music")).unwrap();
+//! let playlist = playlist_localizer::localize(&index, Path::new("/music/playlist.m3u")).unwrap();
+//! for song in playlist.songs() {
+//!     println!("{}", song.display());
+//! }
+//! ```
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use globset::GlobSet;
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use regex::Regex;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use walkdir::WalkDir;
+
+use crate::metadata::SongMetadata;
+use crate::playlist::{Playlist, SourceLine};
+
+pub mod export;
+pub mod itunes;
+pub mod metadata;
+pub mod playlist;
+pub mod rekordbox;
+
+pub const MUSIC_EXTENSIONS: [&str; 12] = [
+    "aac", "aif", "aiff", "ape", "flac", "m4a", "m4b", "mp3", "ogg", "opus", "wav", "wv",
+];
+pub const PLAYLIST_EXTENSIONS: [&str; 5] = ["asx", "m3u", "m3u8", "pls", "wpl"];
+
+/// The songs of a music library by their normalized file stem, and the playlists inside it.
+#[derive(Debug, Default, Clone)]
+pub struct MusicIndex {
+    pub songs: HashMap<OsString, Vec<PathBuf>>,
+    pub playlists: Vec<PathBuf>,
+    /// The absolute paths of the music dirs, which the paths of the songs start with.
+    pub roots: Vec<PathBuf>,
+}
+
+impl MusicIndex {
+    /// The songs with the normalized file stem `key`.
+    pub fn lookup(&self, key: &OsStr) -> Option<&[PathBuf]> {
+        self.songs.get(key).map(Vec::as_slice)
+    }
+
+    /// The number of components of the deepest music dir the `song` is in, 0 if it isn't in any.
+    fn root_len(&self, song: &Path) -> usize {
+        let roots = self.roots.iter().filter(|r| song.starts_with(r));
+        roots.map(|r| r.components().count()).max().unwrap_or(0)
+    }
+
+    /// All songs of the index, in no particular order.
+    pub fn all_songs(&self) -> impl Iterator<Item = &PathBuf> {
+        self.songs.values().flatten()
+    }
+
+    /// Collapses byte-identical songs with the same key to the one with the shortest path, like
+    /// the same song in several albums. Only songs of the same size are hashed, returns the
+    /// number of collapsed groups of identical songs.
+    pub fn dedup(&mut self) -> usize {
+        self.songs
+            .par_iter_mut()
+            .map(|(_, songs)| dedup_identical(songs))
+            .sum()
+    }
+}
+
+/// The songs of a music library by the normalized artist and title of their tags.
+pub type TagIndex = HashMap<(OsString, OsString), Vec<PathBuf>>;
+
+/// Indexes the songs by their normalized file stem and collects the playlists inside the
+/// `music_dirs`. The directories are walked in sequence and merged into a single index.
+pub fn index(music_dirs: &[PathBuf], options: &IndexOptions) -> io::Result<MusicIndex> {
+    index_with_progress(music_dirs, options, &ProgressBar::hidden())
+}
+
+/// Like [`index`], but increments `progress` for every directory entry that is walked.
+pub fn index_with_progress(
+    music_dirs: &[PathBuf],
+    options: &IndexOptions,
+    progress: &ProgressBar,
+) -> io::Result<MusicIndex> {
+    // Without a separate playlist dir the music dirs are searched for both songs and playlists.
+    let mut roots: Vec<(&Path, Scan)> = match &options.playlist_dir {
+        Some(_) => music_dirs
+            .iter()
+            .map(|d| (d.as_path(), Scan::Music))
+            .collect(),
+        None => music_dirs
+            .iter()
+            .map(|d| (d.as_path(), Scan::All))
+            .collect(),
+    };
+    if let Some(playlist_dir) = &options.playlist_dir {
+        roots.push((playlist_dir, Scan::Playlists));
+    }
+
+    let mut entries: Vec<(walkdir::DirEntry, Scan)> = Vec::new();
+    // the real paths of the directories walked so far, so symlinked directories are only entered
+    // once and cycles are skipped
+    let mut visited_dirs = HashSet::new();
+    let mut music_roots = Vec::new();
+    for (root, scan) in roots {
+        let abs_root = options
+            .root_path(root)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", root.display())))?;
+        if !matches!(scan, Scan::Playlists) {
+            music_roots.push(abs_root.clone());
+        }
+        let dir_entries = WalkDir::new(abs_root)
+            .follow_links(options.follow_symlinks)
+            .max_depth(options.max_depth.unwrap_or(usize::MAX))
+            .into_iter()
+            .filter_entry(|e| {
+                if options.is_ignored(e) {
+                    return false;
+                }
+                if !options.follow_symlinks || !e.file_type().is_dir() {
+                    return true;
+                }
+                match canonicalize(e.path()) {
+                    Ok(real_path) => {
+                        let new = visited_dirs.insert(real_path);
+                        if !new {
+                            log::debug!("skipping {}, it was already indexed", e.path().display());
+                        }
+                        new
+                    }
+                    Err(err) => {
+                        log::warn!("skipping {}: {err}", e.path().display());
+                        false
+                    }
+                }
+            })
+            .filter_map(|e| match e {
+                Ok(e) => Some(e),
+                Err(err) => {
+                    match (err.path(), err.loop_ancestor(), err.io_error()) {
+                        (Some(path), Some(ancestor), _) => log::warn!(
+                            "skipping {}, it's a symlink cycle back to {}",
+                            path.display(),
+                            ancestor.display()
+                        ),
+                        (Some(path), _, Some(io)) => {
+                            log::warn!("skipping {}: {io}", path.display())
+                        }
+                        _ => log::warn!("skipping an entry: {err}"),
+                    }
+                    None
+                }
+            })
+            .inspect(|_| progress.inc(1))
+            .map(|e| (e, scan));
+        entries.extend(dir_entries);
+    }
+
+    // Entries are tagged with their traversal index, so the merged results can be restored to the
+    // same order a sequential walk would produce.
+    type Partial = (
+        HashMap<OsString, Vec<(usize, PathBuf)>>,
+        Vec<(usize, PathBuf)>,
+    );
+    let (music_index, playlist_index): Partial = entries
+        .into_par_iter()
+        .enumerate()
+        .filter(|(_, (e, _))| match e.metadata() {
+            Ok(m) => m.is_file(),
+            Err(err) => {
+                log::warn!("skipping {}: {err}", e.path().display());
+                false
+            }
+        })
+        .fold(
+            Partial::default,
+            |(mut music_index, mut playlist_index), (i, (d, scan))| {
+                let path = d.into_path();
+                let Some(file_stem) = path.file_stem() else {
+                    return (music_index, playlist_index);
+                };
+                let extension = match path.extension() {
+                    Some(e) => options.extensions.of(e),
+                    None if options.sniff && !matches!(scan, Scan::Playlists) => {
+                        match is_audio_file(&path) {
+                            true => Extension::Music,
+                            false => Extension::Unknown,
+                        }
+                    }
+                    None => Extension::Unknown,
+                };
+                match (extension, scan) {
+                    (Extension::Music, Scan::All | Scan::Music) if options.is_included(&path) => {
+                        match music_index.entry(options.stem.stem_key(file_stem)) {
+                            hash_map::Entry::Occupied(songs) => {
+                                songs.into_mut().push((i, path));
+                            }
+                            hash_map::Entry::Vacant(v) => {
+                                v.insert(vec![(i, path)]);
+                            }
+                        }
+                    }
+                    (Extension::Playlist, Scan::All | Scan::Playlists) => {
+                        playlist_index.push((i, path))
+                    }
+                    _ => (),
+                }
+                (music_index, playlist_index)
+            },
+        )
+        .reduce(
+            Partial::default,
+            |(mut music_a, mut playlists_a), (music_b, playlists_b)| {
+                for (key, songs) in music_b {
+                    music_a.entry(key).or_default().extend(songs);
+                }
+                playlists_a.extend(playlists_b);
+                (music_a, playlists_a)
+            },
+        );
+
+    let mut music_index: HashMap<OsString, Vec<PathBuf>> = music_index
+        .into_par_iter()
+        .map(|(key, mut songs)| {
+            songs.sort_unstable_by_key(|(i, _)| *i);
+            (key, songs.into_iter().map(|(_, p)| p).collect())
+        })
+        .collect();
+    let mut playlist_index: Vec<PathBuf> = playlist_index.into_iter().map(|(_, p)| p).collect();
+
+    if let Some(keep) = options.collapse_symlinks {
+        for songs in music_index.values_mut() {
+            collapse_symlinks(songs, keep);
+        }
+    }
+
+    // the walk order depends on the file system, so results are sorted to be reproducible
+    music_index
+        .par_iter_mut()
+        .for_each(|(_, songs)| songs.sort());
+    playlist_index.sort();
+
+    Ok(MusicIndex {
+        songs: music_index,
+        playlists: playlist_index,
+        roots: music_roots,
+    })
+}
+
+/// Whether the file starts like one of the common audio containers: mp3 with an ID3 tag or a
+/// frame sync, flac, ogg, mp4, wav or aiff.
+fn is_audio_file(path: &Path) -> bool {
+    let mut magic = [0; 12];
+    let Ok(len) = std::fs::File::open(path).and_then(|f| f.take(12).read(&mut magic)) else {
+        return false;
+    };
+    match &magic[..len] {
+        [b'I', b'D', b'3', ..] | [b'f', b'L', b'a', b'C', ..] | [b'O', b'g', b'g', b'S', ..] => {
+            true
+        }
+        [0xff, b, ..] if b & 0xe0 == 0xe0 => true,
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => true,
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E'] => true,
+        [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', b'F' | b'C'] => true,
+        _ => false,
+    }
+}
+
+/// Removes songs that resolve to the same file as a previous one.
+fn collapse_symlinks(songs: &mut Vec<PathBuf>, keep: SymlinkCollapse) {
+    if songs.len() < 2 {
+        return;
+    }
+
+    // sort first, so the same alias is kept regardless of the directory traversal order
+    songs.sort();
+    let mut real_paths = Vec::with_capacity(songs.len());
+    songs.retain_mut(|s| {
+        let Ok(real_path) = canonicalize(s) else {
+            return true;
+        };
+        if real_paths.contains(&real_path) {
+            return false;
+        }
+        if keep == SymlinkCollapse::Canonical {
+            *s = real_path.clone();
+        }
+        real_paths.push(real_path);
+        true
+    });
+}
+
+/// Removes all but the shortest path of every group of `songs` with the same content, and
+/// returns the number of groups.
+fn dedup_identical(songs: &mut Vec<PathBuf>) -> usize {
+    if songs.len() < 2 {
+        return 0;
+    }
+
+    let sizes: Vec<Option<u64>> = songs
+        .iter()
+        .map(|s| std::fs::metadata(s).ok().map(|m| m.len()))
+        .collect();
+    let mut groups: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+    for (i, song) in songs.iter().enumerate() {
+        let Some(size) = sizes[i] else {
+            continue;
+        };
+        // files with a unique size can't have a duplicate
+        if sizes.iter().filter(|s| **s == Some(size)).count() < 2 {
+            continue;
+        }
+        match content_hash(song) {
+            Ok(hash) => groups.entry((size, hash)).or_default().push(i),
+            Err(e) => log::warn!("can't read {}: {e}", song.display()),
+        }
+    }
+
+    let mut duplicate = vec![false; songs.len()];
+    let mut collapsed = 0;
+    for group in groups.values().filter(|g| g.len() > 1) {
+        let keep = group
+            .iter()
+            .min_by_key(|i| (songs[**i].as_os_str().len(), &songs[**i]));
+        for i in group.iter().filter(|i| Some(*i) != keep) {
+            log::debug!("collapsing identical song {}", songs[*i].display());
+            duplicate[*i] = true;
+        }
+        collapsed += 1;
+    }
+
+    let mut duplicates = duplicate.into_iter();
+    songs.retain(|_| !duplicates.next().unwrap_or(false));
+    collapsed
+}
+
+/// A hash of the contents of the file at `path`.
+fn content_hash(path: &Path) -> io::Result<u64> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.write(&buf[..len]);
+    }
+}
+
+/// Indexes the songs inside `music_dir` using the default options.
+pub fn build_index(music_dir: &Path) -> io::Result<MusicIndex> {
+    index(&[music_dir.to_path_buf()], &IndexOptions::default())
+}
+
+/// Localizes the playlist at `playlist_path` using the default options. Relative entries are
+/// resolved from the playlist's directory, otherwise it behaves like [`localize_str`].
+pub fn localize<'a>(index: &'a MusicIndex, playlist_path: &Path) -> io::Result<Playlist<'a>> {
+    let name = playlist_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let contents = read_playlist(playlist_path)?;
+    let format = PlaylistInputFormat::from_extension(playlist_path.extension());
+
+    Ok(localize_contents(
+        index,
+        &name,
+        &contents,
+        format,
+        playlist_path.parent(),
+    ))
+}
+
+/// Localizes the `content` of a playlist named `name` using the default options, without reading
+/// anything but the index. Nested playlists can't be expanded and are left out.
+pub fn localize_str<'a>(
+    index: &'a MusicIndex,
+    name: &str,
+    content: &str,
+    format: PlaylistInputFormat,
+) -> Playlist<'a> {
+    localize_contents(index, name, content, format, None)
+}
+
+fn localize_contents<'a>(
+    index: &'a MusicIndex,
+    name: &str,
+    content: &str,
+    format: PlaylistInputFormat,
+    base_dir: Option<&Path>,
+) -> Playlist<'a> {
+    let entries = parse_playlist_as(content, format);
+    let entries = skip_nested(entries, name, &Extensions::default());
+    let options = MatchOptions::default();
+    let (playlist, _) = m3u_playlist(
+        &DefaultMatcher,
+        index,
+        None,
+        None,
+        &options,
+        &entries,
+        base_dir,
+        name.to_string(),
+    );
+
+    playlist
+}
+
+/// Reads the entries of the playlist at `playlist_path`, in the format of its extension. Playlists
+/// that can't be read are skipped with a warning.
+pub fn m3u_playlist_paths(playlist_path: &Path) -> Vec<Entry> {
+    let format = PlaylistInputFormat::from_extension(playlist_path.extension());
+    parse_entries(playlist_path, format)
+}
+
+/// Reads the entries of the playlist at `playlist_path` in the given `format`. Playlists that
+/// can't be read are skipped with a warning.
+pub fn parse_entries(playlist_path: &Path, format: PlaylistInputFormat) -> Vec<Entry> {
+    match read_playlist(playlist_path) {
+        Ok(contents) if is_hls(&contents) => {
+            log::warn!(
+                "skipping {}, it's an HLS playlist whose segments can't be localized",
+                playlist_path.display()
+            );
+            Vec::new()
+        }
+        Ok(contents) => parse_playlist_as(&contents, format),
+        Err(e) => {
+            log::warn!("skipping {}: {e}", playlist_path.display());
+            Vec::new()
+        }
+    }
+}
+
+/// Reads the playlist at `playlist_path`, see [`decode_playlist`].
+pub fn read_playlist(playlist_path: &Path) -> io::Result<String> {
+    let bytes = std::fs::read(playlist_path)?;
+    Ok(decode_playlist(&bytes, playlist_path.extension()))
+}
+
+/// Decodes the `bytes` of a playlist. UTF-16 is detected by its byte order mark. Other playlists
+/// are read as UTF-8, falling back to Windows-1252, which older players write `.m3u` files in,
+/// except for `.m3u8` playlists which are always UTF-8.
+pub fn decode_playlist(bytes: &[u8], extension: Option<&OsStr>) -> String {
+    let utf16 = |bytes: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        let units = bytes.chunks_exact(2).map(|c| from_bytes([c[0], c[1]]));
+        char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    };
+    match bytes {
+        [0xff, 0xfe, rest @ ..] => return utf16(rest, u16::from_le_bytes),
+        [0xfe, 0xff, rest @ ..] => return utf16(rest, u16::from_be_bytes),
+        _ => (),
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) if extension.is_some_and(|e| e.eq_ignore_ascii_case("m3u8")) => {
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+        Err(_) => bytes.iter().map(|&b| windows_1252(b)).collect(),
+    }
+}
+
+/// The character of a Windows-1252 byte, undefined bytes are mapped like in Latin-1.
+fn windows_1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+        '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}',
+        'ž', 'Ÿ',
+    ];
+    match byte {
+        0x80..=0x9f => HIGH[byte as usize - 0x80],
+        b => b as char,
+    }
+}
+
+/// Replaces the entries pointing to other playlists with the entries of those playlists, up to
+/// `max_depth` levels deep. Relative paths are resolved from the directory of the playlist at
+/// `playlist_path`, and the relative entries of nested playlists are rewritten to be relative to
+/// it as well. Playlists that aren't found, are nested too deeply, or include themselves,
+/// directly or indirectly, are left out with a warning, so they aren't matched as songs.
+pub fn expand_nested(
+    entries: Vec<Entry>,
+    playlist_path: &Path,
+    extensions: &Extensions,
+    max_depth: usize,
+) -> Vec<Entry> {
+    let mut visited = HashSet::new();
+    if let Ok(p) = canonicalize(playlist_path) {
+        visited.insert(p);
+    }
+    expand_nested_inner(entries, playlist_path, extensions, max_depth, &mut visited)
+}
+
+fn expand_nested_inner(
+    entries: Vec<Entry>,
+    playlist_path: &Path,
+    extensions: &Extensions,
+    depth: usize,
+    visited: &mut HashSet<PathBuf>,
+) -> Vec<Entry> {
+    let dir = playlist_path.parent().unwrap_or(Path::new(""));
+    let mut expanded = Vec::with_capacity(entries.len());
+    for e in entries {
+        if !e.is_playlist(extensions) {
+            expanded.push(e);
+            continue;
+        }
+
+        let nested_path = dir.join(&e.path);
+        let Ok(real_path) = canonicalize(&nested_path) else {
+            log::warn!(
+                "{}: skipping {}, the playlist wasn't found",
+                playlist_path.display(),
+                e.path.display()
+            );
+            continue;
+        };
+        if depth == 0 {
+            log::warn!(
+                "{}: not expanding {}, it is nested too deeply",
+                playlist_path.display(),
+                e.path.display()
+            );
+            continue;
+        }
+        if !visited.insert(real_path.clone()) {
+            log::warn!(
+                "{}: not expanding {}, it would include itself",
+                playlist_path.display(),
+                e.path.display()
+            );
+            continue;
+        }
+
+        let nested = m3u_playlist_paths(&real_path);
+        let mut nested = expand_nested_inner(nested, &real_path, extensions, depth - 1, visited);
+        let nested_dir = e.path.parent().unwrap_or(Path::new(""));
+        for n in nested
+            .iter_mut()
+            .filter(|n| n.path.is_relative() && n.url().is_none())
+        {
+            n.path = normalize_path(&nested_dir.join(&n.path));
+        }
+        if let Some(first) = nested.first_mut() {
+            let mut comments = e.comments;
+            comments.append(&mut first.comments);
+            first.comments = comments;
+        }
+        expanded.append(&mut nested);
+        visited.remove(&real_path);
+    }
+
+    expanded
+}
+
+/// Removes the entries pointing to other playlists from a playlist named `name`, whose nested
+/// playlists can't be read, with a warning.
+pub fn skip_nested(entries: Vec<Entry>, name: &str, extensions: &Extensions) -> Vec<Entry> {
+    entries
+        .into_iter()
+        .filter(|e| {
+            let is_playlist = e.is_playlist(extensions);
+            if is_playlist {
+                log::warn!(
+                    "{name}: skipping {}, nested playlists can't be expanded here",
+                    e.path.display()
+                );
+            }
+            !is_playlist
+        })
+        .collect()
+}
+
+/// Parses the `contents` of a playlist depending on its file `extension`.
+pub fn parse_playlist(contents: &str, extension: Option<&OsStr>) -> Vec<Entry> {
+    parse_playlist_as(contents, PlaylistInputFormat::from_extension(extension))
+}
+
+/// Parses the `contents` of a playlist in the given `format`.
+pub fn parse_playlist_as(contents: &str, format: PlaylistInputFormat) -> Vec<Entry> {
+    // m3u8 playlists are often written with a byte order mark
+    let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+    match format {
+        PlaylistInputFormat::M3u => parse_m3u(contents),
+        PlaylistInputFormat::Pls => parse_pls(contents),
+        PlaylistInputFormat::Wpl => parse_wpl(contents),
+        PlaylistInputFormat::Asx => parse_asx(contents),
+        PlaylistInputFormat::Txt => parse_txt(contents),
+        PlaylistInputFormat::Cue => parse_cue(contents),
+    }
+}
+
+/// The formats playlists can be read from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlaylistInputFormat {
+    /// Plain or extended m3u, which is also used for unknown extensions.
+    #[default]
+    M3u,
+    Pls,
+    Wpl,
+    Asx,
+    /// One path per line.
+    Txt,
+    /// A cue sheet, whose tracks become entries of the files they are in.
+    Cue,
+}
+
+impl PlaylistInputFormat {
+    /// The format of a playlist with the file `extension`, ignoring its ASCII case.
+    pub fn from_extension(extension: Option<&OsStr>) -> Self {
+        match extension.and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("pls") => PlaylistInputFormat::Pls,
+            Some(e) if e.eq_ignore_ascii_case("wpl") => PlaylistInputFormat::Wpl,
+            Some(e) if e.eq_ignore_ascii_case("asx") => PlaylistInputFormat::Asx,
+            Some(e) if e.eq_ignore_ascii_case("txt") => PlaylistInputFormat::Txt,
+            Some(e) if e.eq_ignore_ascii_case("cue") => PlaylistInputFormat::Cue,
+            _ => PlaylistInputFormat::M3u,
+        }
+    }
+}
+
+impl FromStr for PlaylistInputFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "m3u" | "m3u8" => Ok(PlaylistInputFormat::M3u),
+            "pls" => Ok(PlaylistInputFormat::Pls),
+            "wpl" => Ok(PlaylistInputFormat::Wpl),
+            "asx" => Ok(PlaylistInputFormat::Asx),
+            "txt" => Ok(PlaylistInputFormat::Txt),
+            "cue" => Ok(PlaylistInputFormat::Cue),
+            _ => Err("Unknown playlist input format"),
+        }
+    }
+}
+
+/// Parses the `FileN=` entries of a pls playlist ordered by `N`.
+fn parse_pls(contents: &str) -> Vec<Entry> {
+    let mut files: Vec<(u64, PathBuf)> = Vec::new();
+    for l in contents.lines() {
+        let Some((key, value)) = l.trim().split_once('=') else {
+            continue;
+        };
+        let Some(n) = key.strip_prefix("File").and_then(|n| n.trim().parse().ok()) else {
+            continue;
+        };
+        files.push((n, source_path(value.trim())));
+    }
+    files.sort_by_key(|(n, _)| *n);
+
+    files
+        .into_iter()
+        .map(|(_, path)| Entry {
+            path,
+            extinf: None,
+            comments: Vec::new(),
+        })
+        .collect()
+}
+
+/// Parses a plain list with one path per line, blank lines and comments starting with `#` are
+/// ignored.
+fn parse_txt(contents: &str) -> Vec<Entry> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.starts_with('#'))
+        .map(unquote)
+        .filter(|l| !l.is_empty())
+        .map(|l| Entry {
+            path: source_path(l),
+            extinf: None,
+            comments: Vec::new(),
+        })
+        .collect()
+}
+
+/// Parses the tracks of a cue sheet into entries of the `FILE` they are in, with the `TITLE` and
+/// `PERFORMER` of the track, or of the sheet if the track has none. Tracks of the same file are
+/// repeated entries of it, whose duration is the distance to the `INDEX 01` of the next track.
+fn parse_cue(contents: &str) -> Vec<Entry> {
+    struct Track {
+        file: usize,
+        start: Option<u64>,
+        extinf: ExtInf,
+    }
+
+    let mut files: Vec<String> = Vec::new();
+    let mut tracks: Vec<Track> = Vec::new();
+    let mut performer = None;
+    for l in contents.lines() {
+        let (command, args) = l.trim().split_once(' ').unwrap_or((l.trim(), ""));
+        let args = args.trim();
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => files.push(cue_value(args)),
+            "TRACK" if !files.is_empty() => tracks.push(Track {
+                file: files.len() - 1,
+                start: None,
+                extinf: ExtInf::default(),
+            }),
+            "TITLE" => {
+                if let Some(t) = tracks.last_mut() {
+                    t.extinf.title = Some(cue_value(args));
+                }
+            }
+            "PERFORMER" => match tracks.last_mut() {
+                Some(t) => t.extinf.artist = Some(cue_value(args)),
+                None => performer = Some(cue_value(args)),
+            },
+            "INDEX" => {
+                let (number, time) = args.split_once(' ').unwrap_or((args, ""));
+                if let (Some(t), "01") = (tracks.last_mut(), number) {
+                    t.start = cue_frames(time.trim());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut results = Vec::with_capacity(tracks.len());
+    for (i, t) in tracks.iter().enumerate() {
+        let next = tracks.get(i + 1).filter(|n| n.file == t.file);
+        // cue sheets count in frames of 1/75 seconds
+        let duration = match (t.start, next.and_then(|n| n.start)) {
+            (Some(start), Some(end)) => Some((end.saturating_sub(start) as i64 + 37) / 75),
+            _ => None,
+        };
+        let extinf = ExtInf {
+            duration,
+            artist: t.extinf.artist.clone().or_else(|| performer.clone()),
+            title: t.extinf.title.clone(),
+        };
+        // the extm3u format reads the metadata of songs from the source's directives
+        results.push(Entry {
+            path: source_path(&files[t.file]),
+            comments: vec![extinf.line()],
+            extinf: Some(extinf),
+        });
+    }
+    results
+}
+
+/// The value of a cue sheet command, which is quoted if it contains spaces. The file type after
+/// the name of a `FILE` command is dropped.
+fn cue_value(args: &str) -> String {
+    match args.strip_prefix('"') {
+        Some(quoted) => quoted[..quoted.find('"').unwrap_or(quoted.len())].to_string(),
+        None => args.split(' ').next().unwrap_or_default().to_string(),
+    }
+}
+
+/// The number of frames of a cue sheet time like `03:25:12`, in minutes, seconds and frames.
+fn cue_frames(time: &str) -> Option<u64> {
+    let mut parts = time.splitn(3, ':').map(|p| p.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    Some((minutes * 60 + seconds) * 75 + frames)
+}
+
+/// Parses the `src` attributes of the `<media>` elements inside the `<seq>` element of a Windows
+/// Media Player playlist.
+fn parse_wpl(contents: &str) -> Vec<Entry> {
+    let seq = match contents.find("<seq") {
+        Some(start) => {
+            let seq = &contents[start..];
+            &seq[..seq.find("</seq>").unwrap_or(seq.len())]
+        }
+        None => contents,
+    };
+
+    let mut results = Vec::new();
+    let mut rest = seq;
+    while let Some(start) = rest.find("<media") {
+        rest = &rest[start + "<media".len()..];
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+        if let Some(src) = xml_attribute(tag, "src") {
+            results.push(Entry {
+                path: source_path(&xml_unescape(src)),
+                extinf: None,
+                comments: Vec::new(),
+            });
+        }
+    }
+    results
+}
+
+/// Parses the `href` attributes of the `<ref>` elements of an asx playlist. Its tags and attributes
+/// are case insensitive. `file://` URIs are decoded, http and https URLs are kept, and refs with
+/// other schemes like `mms://` are skipped.
+fn parse_asx(contents: &str) -> Vec<Entry> {
+    // ascii lowercasing keeps the byte offsets the same
+    let lowercase = contents.to_ascii_lowercase();
+
+    let mut results = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = lowercase[offset..].find("<ref") {
+        let tag_start = offset + start + "<ref".len();
+        let tag_len = contents[tag_start..]
+            .find('>')
+            .unwrap_or(contents.len() - tag_start);
+        let tag = &contents[tag_start..tag_start + tag_len];
+        offset = tag_start + tag_len;
+
+        let Some(href) = xml_attribute(tag, "href").map(xml_unescape) else {
+            continue;
+        };
+        let path = match itunes::file_url_path(&href) {
+            Some(p) => p,
+            None if href.contains("://") && !is_url(&href) => continue,
+            None => href,
+        };
+        results.push(Entry {
+            path: source_path(&path),
+            extinf: None,
+            comments: Vec::new(),
+        });
+    }
+    results
+}
+
+/// The raw value of the attribute `name` inside the contents of an xml `tag`, attribute names are
+/// compared case insensitively.
+pub(crate) fn xml_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    loop {
+        let (key, value) = rest.split_once('=')?;
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &value[1..];
+        let end = value.find(quote)?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            return Some(&value[..end]);
+        }
+        rest = &value[end + 1..];
+    }
+}
+
+/// Decodes the predefined xml entities and character references.
+pub(crate) fn xml_unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                r => {
+                    let code = match r.strip_prefix("#x").or_else(|| r.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => r.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+fn parse_m3u(contents: &str) -> Vec<Entry> {
+    // the segments of HLS streams share the m3u8 syntax, but aren't songs
+    if is_hls(contents) {
+        return Vec::new();
+    }
+
+    let mut results: Vec<Entry> = Vec::new();
+    let mut extinf = None;
+    let mut comments = Vec::new();
+    // old mac exporters end lines with a lone `\r`
+    for l in contents.split(['\n', '\r']).map(str::trim) {
+        if l.is_empty() || l == "#EXTM3U" {
+            continue;
+        }
+        if let Some(info) = l.strip_prefix("#EXTINF:") {
+            extinf = Some(ExtInf::parse(info));
+        }
+        if l.starts_with('#') {
+            comments.push(l.to_string());
+            continue;
+        }
+        // an empty quoted path would otherwise look like the comments after the last song
+        let path = unquote(l);
+        if !path.is_empty() {
+            results.push(Entry {
+                path: source_path(path),
+                extinf: extinf.take(),
+                comments: std::mem::take(&mut comments),
+            });
+        }
+    }
+    if !comments.is_empty() {
+        results.push(Entry {
+            path: PathBuf::new(),
+            extinf: None,
+            comments,
+        });
+    }
+
+    results
+}
+
+/// Whether the `contents` are an HLS media or master playlist, which contain `#EXT-X-` tags.
+pub fn is_hls(contents: &str) -> bool {
+    contents.lines().any(|l| l.starts_with("#EXT-X-"))
+}
+
+/// Strips a single pair of double quotes around a path, which some exporters add. A lone quote
+/// isn't a pair and is kept.
+fn unquote(s: &str) -> &str {
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(unquoted) => unquoted.trim(),
+        None => s,
+    }
+}
+
+/// Converts a path of a source playlist, URLs are kept verbatim.
+fn source_path(s: &str) -> PathBuf {
+    match is_url(s) {
+        true => PathBuf::from(s),
+        false => platform_path(s),
+    }
+}
+
+fn is_url(s: &str) -> bool {
+    let scheme = s.split_once("://").map(|(scheme, _)| scheme);
+    scheme.is_some_and(|s| s.eq_ignore_ascii_case("http") || s.eq_ignore_ascii_case("https"))
+}
+
+/// A song entry of a source playlist. The comments after the last song are kept in an entry with
+/// an empty path.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub extinf: Option<ExtInf>,
+    /// The comment and directive lines preceding the path, including the `#EXTINF` line.
+    pub comments: Vec<String>,
+}
+
+/// The information of an `#EXTINF:<duration>,<artist> - <title>` directive.
+#[derive(Clone, Debug, Default)]
+pub struct ExtInf {
+    /// The duration in seconds, `-1` if unknown.
+    pub duration: Option<i64>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+impl Entry {
+    /// The path if it is an http or https URL.
+    pub fn url(&self) -> Option<&str> {
+        self.path.to_str().filter(|p| is_url(p))
+    }
+
+    /// Whether this only holds the comments after the last song.
+    pub fn is_trailer(&self) -> bool {
+        self.path.as_os_str().is_empty()
+    }
+
+    /// Whether this points to another playlist instead of a song.
+    pub fn is_playlist(&self, extensions: &Extensions) -> bool {
+        self.url().is_none()
+            && self
+                .path
+                .extension()
+                .is_some_and(|ext| matches!(extensions.of(ext), Extension::Playlist))
+    }
+}
+
+impl ExtInf {
+    /// Parses the part of the directive after `#EXTINF:`.
+    pub fn parse(info: &str) -> Self {
+        let (duration, display) = info.split_once(',').unwrap_or((info, ""));
+        let duration = duration.trim().parse().ok();
+        let Some((artist, title)) = display.split_once(" - ") else {
+            let title = display.trim();
+            return Self {
+                duration,
+                artist: None,
+                title: Some(title.to_string()).filter(|_| !title.is_empty()),
+            };
+        };
+
+        Self {
+            duration,
+            artist: Some(artist.trim().to_string()),
+            title: Some(title.trim().to_string()),
+        }
+    }
+
+    /// The metadata described by the directive, unknown fields are left empty.
+    pub fn metadata(&self) -> SongMetadata {
+        SongMetadata {
+            title: self.title.clone().unwrap_or_default(),
+            artists: self.artist.iter().cloned().collect(),
+            duration_ms: self
+                .duration
+                .and_then(|d| u64::try_from(d).ok())
+                .map_or(0, |d| d * 1000),
+            ..Default::default()
+        }
+    }
+
+    /// The whole `#EXTINF:` line of the directive, the inverse of [`ExtInf::parse`].
+    pub fn line(&self) -> String {
+        let duration = self.duration.unwrap_or(-1);
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("#EXTINF:{duration},{artist} - {title}"),
+            (None, Some(title)) => format!("#EXTINF:{duration},{title}"),
+            (Some(artist), None) => format!("#EXTINF:{duration},{artist} - "),
+            (None, None) => format!("#EXTINF:{duration},"),
+        }
+    }
+}
+
+/// Reads the names and song paths of all playlists inside the zip archive at `path`.
+pub fn zip_playlists(
+    path: &Path,
+    extensions: &Extensions,
+) -> zip::result::ZipResult<Vec<(String, Vec<Entry>)>> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+    let mut playlists = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let Some(entry_path) = entry.enclosed_name() else {
+            continue;
+        };
+        let is_playlist = entry_path
+            .extension()
+            .is_some_and(|e| matches!(extensions.of(e), Extension::Playlist));
+        let name = entry_path.file_stem().and_then(|s| s.to_str());
+        let (true, Some(name)) = (entry.is_file() && is_playlist, name) else {
+            continue;
+        };
+        let name = name.to_string();
+
+        let mut bytes = Vec::new();
+        if entry.read_to_end(&mut bytes).is_ok() {
+            let contents = decode_playlist(&bytes, entry_path.extension());
+            if is_hls(&contents) {
+                log::warn!(
+                    "{name}: skipping, it's an HLS playlist whose segments can't be localized"
+                );
+                continue;
+            }
+            let entries = parse_playlist(&contents, entry_path.extension());
+            let entries = skip_nested(entries, &name, extensions);
+            playlists.push((name, entries));
+        }
+    }
+
+    Ok(playlists)
+}
+
+/// Localizes the `entries` of a playlist. Relative entries are matched as if they were joined to
+/// the `base_dir`, usually the playlist's directory. Songs that aren't found in the `index` are
+/// looked up in the `fallback` index, and then by the artist and title of their `#EXTINF`
+/// directive in the `tags` index.
+#[allow(clippy::too_many_arguments)]
+pub fn m3u_playlist<'a>(
+    matcher: &dyn SongMatcher,
+    index: &'a MusicIndex,
+    fallback: Option<&'a MusicIndex>,
+    tags: Option<&'a TagIndex>,
+    options: &MatchOptions,
+    entries: &[Entry],
+    base_dir: Option<&Path>,
+    name: String,
+) -> (Playlist<'a>, MatchStats<'a>) {
+    let mut stats = MatchStats::default();
+    let mut songs = Vec::with_capacity(entries.len());
+    let mut unmatched = Vec::new();
+    let mut source = Vec::new();
+    for p in entries {
+        if p.is_trailer() {
+            source.extend(p.comments.iter().cloned().map(SourceLine::Comment));
+            continue;
+        }
+        if let Some(url) = p.url() {
+            if options.keep_urls {
+                source.extend(p.comments.iter().cloned().map(SourceLine::Comment));
+                source.push(SourceLine::Url(url.to_string()));
+            }
+            continue;
+        }
+
+        // relative entries are matched as the absolute paths they point to from the `base_dir`, so
+        // all of their parent directories can be compared
+        let resolved;
+        let entry = match base_dir {
+            Some(base) if p.path.is_relative() => {
+                resolved = Entry {
+                    path: normalize_path(&base.join(&p.path)),
+                    extinf: p.extinf.clone(),
+                    comments: Vec::new(),
+                };
+                &resolved
+            }
+            _ => p,
+        };
+
+        let mut rejected = false;
+        let mut candidates = 0;
+        let mut confidence = None;
+        let mut from_fallback = false;
+        let mut accept = |m: Option<FileMatch<'a>>| {
+            let m = m?;
+            candidates = m.candidates;
+            let components = m.matching_components < options.min_matching_components as f64;
+            if m.confidence() < options.min_confidence || components {
+                rejected = true;
+                return None;
+            }
+            confidence = Some(m.confidence());
+            Some(m.path)
+        };
+
+        let song = accept(matcher.match_file(index, options, entry))
+            .or_else(|| {
+                let song = accept(matcher.match_file(fallback?, options, entry))?;
+                stats.fallback += 1;
+                from_fallback = true;
+                Some(song)
+            })
+            .or_else(|| {
+                let song = match_tags(tags?, options, entry)?;
+                stats.tags += 1;
+                Some(song)
+            })
+            .filter(|s| {
+                let playable = !options.verify_matches || is_audio_file(s);
+                if !playable {
+                    log::warn!("{} doesn't look like an audio file", s.display());
+                    stats.unverified += 1;
+                }
+                playable
+            });
+        if song.is_some_and(|s| options.is_excluded(s)) {
+            stats.excluded += 1;
+            // the `#EXTINF` line would otherwise describe the next song
+            let comments = p.comments.iter().filter(|c| !c.starts_with("#EXTINF:"));
+            source.extend(comments.cloned().map(SourceLine::Comment));
+            continue;
+        }
+        stats.entries.push(EntryMatch {
+            source: p.path.clone(),
+            resolved: song,
+            candidates,
+            confidence: confidence.filter(|_| song.is_some()),
+            fallback: from_fallback && song.is_some(),
+        });
+
+        match song {
+            Some(s) => {
+                source.extend(p.comments.iter().map(|c| localize_directive(c, s)));
+                source.push(SourceLine::Song(songs.len()));
+                songs.push(s);
+            }
+            None => {
+                stats.low_confidence += rejected as usize;
+                // the `#EXTINF` line would otherwise describe the next song
+                let comments = p.comments.iter().filter(|c| !c.starts_with("#EXTINF:"));
+                source.extend(comments.cloned().map(SourceLine::Comment));
+                source.push(SourceLine::NotFound(p.path.clone()));
+                unmatched.push(p.path.clone());
+            }
+        }
+    }
+
+    let playlist = Playlist::new(name, songs)
+        .with_unmatched(unmatched)
+        .with_source(source);
+    (playlist, stats)
+}
+
+/// Resolves the image of an `#EXTIMG:` directive before the local `song` to a file with the same
+/// name in the song's directory or the one above it, where album art is usually kept. URLs,
+/// other comments and images that aren't found are kept unchanged.
+fn localize_directive(comment: &str, song: &Path) -> SourceLine {
+    let image = comment
+        .strip_prefix("#EXTIMG:")
+        .map(str::trim)
+        .filter(|i| !i.is_empty() && !i.contains("://"));
+    let local = image.and_then(|i| {
+        let file_name = platform_path(i).file_name()?.to_os_string();
+        let dirs = song.ancestors().skip(1).take(2);
+        dirs.map(|d| d.join(&file_name)).find(|p| p.is_file())
+    });
+
+    match local {
+        Some(p) => SourceLine::Image(p),
+        None => SourceLine::Comment(comment.to_string()),
+    }
+}
+
+/// Statistics about the localization of a single playlist.
+#[derive(Debug, Default)]
+pub struct MatchStats<'a> {
+    /// Songs found in the fallback index.
+    pub fallback: usize,
+    /// Songs whose best match was rejected because of a low confidence or too few matching
+    /// directory components.
+    pub low_confidence: usize,
+    /// Songs found by their tags.
+    pub tags: usize,
+    /// Matched songs that were dropped because they don't look like audio files.
+    pub unverified: usize,
+    /// Matched songs that were left out because they are excluded.
+    pub excluded: usize,
+    /// How each entry of the source playlist was matched, URLs and excluded songs aren't
+    /// included.
+    pub entries: Vec<EntryMatch<'a>>,
+}
+
+/// How an entry of a source playlist was matched.
+#[derive(Debug)]
+pub struct EntryMatch<'a> {
+    pub source: PathBuf,
+    pub resolved: Option<&'a Path>,
+    /// The number of local songs that were considered for the best attempt.
+    pub candidates: usize,
+    /// The [`FileMatch::confidence`] of the resolved song, `None` if it was found by its tags or
+    /// not at all.
+    pub confidence: Option<u8>,
+    /// Whether the resolved song was found in the fallback index.
+    pub fallback: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct MatchOptions {
+    pub stem: StemOptions,
+    /// The minimum similarity of fuzzy stem matches, `None` disables fuzzy matching.
+    pub fuzzy: Option<f64>,
+    /// The minimum similarity of directory components that count as a partial match, `None`
+    /// only counts equal components.
+    pub fuzzy_dirs: Option<f64>,
+    /// Matches whose [`FileMatch::confidence`] is below this score are treated as not found.
+    pub min_confidence: u8,
+    /// Reject matches with fewer matching directory components.
+    pub min_matching_components: usize,
+    pub prefer_artist_folder: bool,
+    /// Keep http and https URLs in the localized playlist, instead of skipping them.
+    pub keep_urls: bool,
+    /// Lowercase extensions ordered by preference, used to choose between songs that match
+    /// equally well.
+    pub prefer_extensions: Vec<String>,
+    /// Prefer songs with the extension of the source entry over ones with more matching
+    /// directory components.
+    pub extension_priority: bool,
+    /// Leave out matched songs with one of these stems, compared like the keys of the index.
+    pub exclude_stems: Vec<OsString>,
+    /// Leave out matched songs whose path matches one of these regexes.
+    pub exclude_paths: Vec<Regex>,
+    /// Check that a candidate is still a regular file before matching it, so the next best one
+    /// is picked for songs that were deleted after indexing.
+    pub verify_exists: bool,
+    /// Drop matched songs whose header doesn't look like a known audio container, like empty or
+    /// corrupt placeholder files.
+    pub verify_matches: bool,
+}
+
+impl MatchOptions {
+    /// Whether the matched local `song` is left out of the localized playlist.
+    fn is_excluded(&self, song: &Path) -> bool {
+        let stem = song.file_stem().unwrap_or_default();
+        self.exclude_stems
+            .iter()
+            .any(|s| self.stem.matches(s, stem))
+            || self
+                .exclude_paths
+                .iter()
+                .any(|r| r.is_match(&song.to_string_lossy()))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IndexOptions {
+    pub stem: StemOptions,
+    pub extensions: Extensions,
+    pub collapse_symlinks: Option<SymlinkCollapse>,
+    /// Follow symlinks while walking, directories which were already visited are skipped.
+    pub follow_symlinks: bool,
+    /// Search this directory for playlists instead of the music dirs.
+    pub playlist_dir: Option<PathBuf>,
+    /// Skip files and directories whose name starts with a dot.
+    pub skip_hidden: bool,
+    /// Skip directories with one of these names, like `@eaDir`.
+    pub ignore_dirs: Vec<OsString>,
+    /// How deep directories are walked, 1 only indexes the files directly inside the roots.
+    pub max_depth: Option<usize>,
+    /// Only index music files whose absolute path matches one of these globs, if there are any.
+    pub include: GlobSet,
+    /// Skip files and directories whose absolute path matches one of these globs, this takes
+    /// precedence over `include`.
+    pub exclude: GlobSet,
+    /// Only make the music and playlist dirs absolute instead of resolving symlinks in them, so
+    /// the indexed paths start with the dirs as they were passed.
+    pub no_canonicalize: bool,
+    /// Index files without an extension as music if their first bytes look like an audio file.
+    pub sniff: bool,
+}
+
+impl IndexOptions {
+    /// The absolute path of a music or playlist dir, as it's used for the indexed paths.
+    pub fn root_path(&self, dir: &Path) -> io::Result<PathBuf> {
+        match self.no_canonicalize {
+            true => std::path::absolute(dir),
+            false => canonicalize(dir),
+        }
+    }
+
+    /// Whether the entry and everything below it is skipped while walking.
+    fn is_ignored(&self, entry: &walkdir::DirEntry) -> bool {
+        // the roots are always walked
+        if entry.depth() == 0 {
+            return false;
+        }
+
+        if self.exclude.is_match(entry.path()) {
+            return true;
+        }
+
+        let name = entry.file_name();
+        let hidden = self.skip_hidden && name.as_encoded_bytes().starts_with(b".");
+        hidden || entry.file_type().is_dir() && self.ignore_dirs.iter().any(|d| d == name)
+    }
+
+    /// Whether the music file matches the include globs.
+    fn is_included(&self, path: &Path) -> bool {
+        self.include.is_empty() || self.include.is_match(path)
+    }
+}
+
+/// Which files are collected from a directory while indexing.
+#[derive(Clone, Copy, Debug)]
+enum Scan {
+    All,
+    Music,
+    Playlists,
+}
+
+/// Which path to keep when multiple songs resolve to the same file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymlinkCollapse {
+    Alias,
+    Canonical,
+}
+
+impl FromStr for SymlinkCollapse {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alias" => Ok(SymlinkCollapse::Alias),
+            "canonical" => Ok(SymlinkCollapse::Canonical),
+            _ => Err("Unknown symlink collapse mode"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Extension {
+    Music,
+    Playlist,
+    Unknown,
+}
+
+/// The file extensions which are recognized while indexing.
+#[derive(Clone, Debug)]
+pub struct Extensions {
+    pub music: Vec<String>,
+    pub playlist: Vec<String>,
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Self {
+            music: MUSIC_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            playlist: PLAYLIST_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+impl Extensions {
+    /// Classifies a file extension, ignoring its ASCII case.
+    #[inline]
+    pub fn of(&self, s: &OsStr) -> Extension {
+        let Some(s) = s.to_str() else {
+            return Extension::Unknown;
+        };
+        for e in self.music.iter() {
+            if s.eq_ignore_ascii_case(e) {
+                return Extension::Music;
+            }
+        }
+        for e in self.playlist.iter() {
+            if s.eq_ignore_ascii_case(e) {
+                return Extension::Playlist;
+            }
+        }
+
+        Extension::Unknown
+    }
+}
+
+/// Controls how file stems are turned into the keys of the music index.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StemOptions {
+    pub fold_accents: bool,
+    pub ignore_case: bool,
+    /// Strip a leading track number like `01. `, `1 - ` or `03_` from file stems.
+    pub strip_track_prefix: bool,
+}
+
+impl StemOptions {
+    /// Normalizes a stem to NFC, so differently composed characters match, and applies the
+    /// enabled foldings.
+    pub fn key(&self, stem: &OsStr) -> OsString {
+        let Some(s) = stem.to_str() else {
+            return stem.to_owned();
+        };
+
+        let mut key = Cow::Borrowed(s);
+        if self.fold_accents {
+            key = Cow::Owned(s.nfd().filter(|c| !is_combining_mark(*c)).nfc().collect());
+        } else if !is_nfc(s) {
+            key = Cow::Owned(s.nfc().collect());
+        }
+        if self.ignore_case {
+            key = Cow::Owned(caseless::default_case_fold_str(&key));
+        }
+
+        OsString::from(key.into_owned())
+    }
+
+    /// Whether two path components are equal after normalization.
+    pub fn matches(&self, a: &OsStr, b: &OsStr) -> bool {
+        a == b || self.key(a) == self.key(b)
+    }
+
+    /// The key of a file stem in the music index. Like [`StemOptions::key`], but also strips the
+    /// track number prefix if enabled, which only file stems and not directories have.
+    pub fn stem_key(&self, stem: &OsStr) -> OsString {
+        let stem = match (self.strip_track_prefix, stem.to_str()) {
+            (true, Some(s)) => OsStr::new(strip_track_prefix(s)),
+            _ => stem,
+        };
+        self.key(stem)
+    }
+}
+
+/// Removes a leading track number of 1 to 3 digits and the dots, dashes, underscores and spaces
+/// after it, unless nothing else is left.
+fn strip_track_prefix(stem: &str) -> &str {
+    let digits = stem.bytes().take_while(u8::is_ascii_digit).count();
+    if !(1..=3).contains(&digits) {
+        return stem;
+    }
+    let rest = stem[digits..].trim_start_matches(['.', '-', '_', ' ']);
+    match rest.len() < stem.len() - digits && !rest.is_empty() {
+        true => rest,
+        false => stem,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileMatch<'a> {
+    pub stem_exact: bool,
+    /// The similarity of the normalized stems, below 1 for fuzzy matches.
+    pub stem_similarity: f64,
+    pub extension_matches: bool,
+    /// The number of matching directory components, where components that only match fuzzily
+    /// count as their similarity.
+    pub matching_components: f64,
+    /// The parent or grandparent directory is named after the artist of the source entry.
+    pub artist_folder: bool,
+    /// The whole parent path below the music dir matches the trailing directories of the source
+    /// entry. Songs outside of the music dirs have to match with their whole parent path.
+    pub parent_matches: bool,
+    /// Higher for extensions earlier in the preferred extensions, 0 if it isn't one of them.
+    pub extension_preference: usize,
+    /// The number of local songs that were considered.
+    pub candidates: usize,
+    pub path: &'a Path,
+}
+
+impl FileMatch<'_> {
+    /// A score from 0 to 100 combining all match signals:
+    ///
+    /// - 40 points if the stem matches exactly, 30 if it only matches after normalization, and 30
+    ///   scaled by the similarity for fuzzy matches
+    /// - 15 points per matching directory component, up to 45
+    /// - 15 points if the extension matches
+    pub fn confidence(&self) -> u8 {
+        let stem = if self.stem_exact {
+            40
+        } else {
+            (30.0 * self.stem_similarity).round() as u8
+        };
+        let components = (15.0 * self.matching_components.min(3.0)).round() as u8;
+        let extension = if self.extension_matches { 15 } else { 0 };
+
+        stem + components + extension
+    }
+
+    /// The number of matching directory components, including the artist folder bonus.
+    pub fn score(&self) -> f64 {
+        self.matching_components + self.artist_folder as u8 as f64
+    }
+}
+
+/// Finds the local song of a source playlist entry in a music index.
+pub trait SongMatcher: Sync {
+    /// The best match for `entry` in `index`, which is rejected by the caller if its
+    /// [`FileMatch::confidence`] is below [`MatchOptions::min_confidence`].
+    fn match_file<'index>(
+        &self,
+        index: &'index MusicIndex,
+        options: &MatchOptions,
+        entry: &Entry,
+    ) -> Option<FileMatch<'index>>;
+}
+
+/// Looks up the normalized stem of the entry, or a similar one if fuzzy matching is enabled, and
+/// picks the local song with the most matching directory components.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultMatcher;
+
+impl SongMatcher for DefaultMatcher {
+    #[inline]
+    fn match_file<'index>(
+        &self,
+        index: &'index MusicIndex,
+        options: &MatchOptions,
+        entry: &Entry,
+    ) -> Option<FileMatch<'index>> {
+        match_file(index, options, entry)
+    }
+}
+
+#[inline]
+fn match_file<'index>(
+    index: &'index MusicIndex,
+    options: &MatchOptions,
+    entry: &Entry,
+) -> Option<FileMatch<'index>> {
+    let stem_options = &options.stem;
+    let file_path = entry.path.as_path();
+    let artist = entry
+        .extinf
+        .as_ref()
+        .and_then(|e| e.artist.as_deref())
+        .filter(|_| options.prefer_artist_folder);
+    let file_stem = file_path.file_stem()?;
+    let key = stem_options.stem_key(file_stem);
+    let (local_songs, stem_similarity) = match index.lookup(&key) {
+        Some(songs) => (songs, 1.0),
+        None => fuzzy_lookup(index, &key, options.fuzzy?)?,
+    };
+
+    let file_extension = file_path.extension()?;
+
+    // the normalized keys of the entry's components don't depend on the local song, so they are
+    // only computed once instead of for every candidate
+    let matches_key =
+        |local: &OsStr, raw: &OsStr, key: &OsStr| local == raw || stem_options.key(local) == key;
+    let file_components: Vec<(&OsStr, OsString)> = file_path
+        .components()
+        .rev()
+        .skip(1)
+        .map(|c| (c.as_os_str(), stem_options.key(c.as_os_str())))
+        .collect();
+    let file_extension_key = stem_options.key(file_extension);
+    let artist_key = artist.map(|a| (OsStr::new(a), stem_options.key(a.as_ref())));
+
+    let mut best_match: Option<FileMatch> = None;
+    for local_path in local_songs.iter() {
+        // the index could be stale, if songs were deleted or replaced by a directory since
+        if options.verify_exists && !local_path.is_file() {
+            log::debug!("skipping {}, it no longer exists", local_path.display());
+            continue;
+        }
+        // songs without an extension are only indexed with `sniff`
+        let local_extension = local_path.extension().unwrap_or_default();
+
+        let local_components = local_path.components().rev().skip(1);
+        let similarities = local_components
+            .zip(file_components.iter())
+            .map(
+                |(l, (raw, key))| match matches_key(l.as_os_str(), raw, key) {
+                    true => 1.0,
+                    false => options.fuzzy_dirs.map_or(0.0, |threshold| {
+                        let similarity =
+                            component_similarity(&stem_options.key(l.as_os_str()), key);
+                        if similarity >= threshold {
+                            similarity
+                        } else {
+                            0.0
+                        }
+                    }),
+                },
+            )
+            .take_while(|s| *s > 0.0);
+        // the number of trailing directories that are equal, before the first fuzzy match
+        let (mut exact_components, mut matching_components) = (0, 0.0);
+        for similarity in similarities {
+            if similarity == 1.0 && exact_components as f64 == matching_components {
+                exact_components += 1;
+            }
+            matching_components += similarity;
+        }
+        // the source entry is usually relative or from another machine, so only the directories
+        // below the music dir have to match
+        let local_parent_len = local_path.components().count() - 1 - index.root_len(local_path);
+
+        let artist_folder = artist_key.as_ref().is_some_and(|(raw, key)| {
+            let mut dirs = local_path.ancestors().skip(1).take(2);
+            dirs.any(|d| d.file_name().is_some_and(|n| matches_key(n, raw, key)))
+        });
+
+        let file_match = FileMatch {
+            stem_exact: local_path.file_stem() == Some(file_stem),
+            stem_similarity,
+            extension_matches: matches_key(local_extension, file_extension, &file_extension_key),
+            matching_components,
+            artist_folder,
+            extension_preference: extension_preference(&options.prefer_extensions, local_extension),
+            candidates: local_songs.len(),
+            parent_matches: local_parent_len > 0 && exact_components >= local_parent_len,
+            path: local_path,
+        };
+
+        // ties are broken by the preferred extensions, the extension of the source entry, then
+        // the whole parent path, and finally the path itself so the result doesn't depend on the
+        // order of the index
+        let is_better = match &best_match {
+            None => true,
+            Some(best) => {
+                let rank = |m: &FileMatch| {
+                    let extension = (m.extension_preference, m.extension_matches);
+                    let extension_first = options.extension_priority && m.extension_matches;
+                    (extension_first, m.score(), extension, m.parent_matches)
+                };
+                let (a, b) = (rank(&file_match), rank(best));
+                let ordering = (a.0.cmp(&b.0))
+                    .then(a.1.total_cmp(&b.1))
+                    .then((a.2, a.3).cmp(&(b.2, b.3)));
+                match ordering {
+                    Ordering::Greater => true,
+                    Ordering::Less => false,
+                    Ordering::Equal => file_match.path < best.path,
+                }
+            }
+        };
+        if is_better {
+            best_match = Some(file_match);
+        }
+    }
+
+    best_match
+}
+
+/// The jaro-winkler similarity of two normalized directory names, which weighs a common prefix
+/// higher, so names with a suffix like `Greatest Hits (Deluxe)` are still similar.
+fn component_similarity(a: &OsStr, b: &OsStr) -> f64 {
+    match (a.to_str(), b.to_str()) {
+        (Some(a), Some(b)) => strsim::jaro_winkler(a, b),
+        _ => 0.0,
+    }
+}
+
+fn extension_preference(preferred: &[String], extension: &OsStr) -> usize {
+    let Some(extension) = extension.to_str() else {
+        return 0;
+    };
+    preferred
+        .iter()
+        .position(|p| p.eq_ignore_ascii_case(extension))
+        .map_or(0, |i| preferred.len() - i)
+}
+
+/// The songs of `index` grouped by the directory that directly contains them, sorted by the
+/// directory and then by the path.
+pub fn song_folders(index: &MusicIndex) -> Vec<(&Path, Vec<&Path>)> {
+    let mut folders: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+    for song in index.all_songs() {
+        if let Some(dir) = song.parent() {
+            folders.entry(dir).or_default().push(song);
+        }
+    }
+    folders
+        .into_iter()
+        .map(|(dir, mut songs)| {
+            songs.sort_unstable();
+            (dir, songs)
+        })
+        .collect()
+}
+
+/// The stems of `index` that belong to songs in more than one directory, sorted by the stem.
+pub fn duplicate_stems(index: &MusicIndex) -> Vec<(&OsStr, &[PathBuf])> {
+    let mut duplicates: Vec<(&OsStr, &[PathBuf])> = index
+        .songs
+        .iter()
+        .filter(|(_, songs)| {
+            let dirs: HashSet<Option<&Path>> = songs.iter().map(|s| s.parent()).collect();
+            dirs.len() > 1
+        })
+        .map(|(stem, songs)| (stem.as_os_str(), songs.as_slice()))
+        .collect();
+    duplicates.sort_unstable_by_key(|(stem, _)| *stem);
+    duplicates
+}
+
+/// Groups the `songs` by their size and a hash of their first 64 KiB, songs in the same group
+/// most likely have the same content. Groups are ordered by their first song, unreadable songs
+/// are left out.
+pub fn content_groups(songs: &[PathBuf]) -> Vec<Vec<&Path>> {
+    const PREFIX_LEN: u64 = 64 * 1024;
+
+    let hashes: Vec<Option<(u64, u64)>> = songs
+        .par_iter()
+        .map(|p| {
+            let file = std::fs::File::open(p).ok()?;
+            let len = file.metadata().ok()?.len();
+            let mut prefix = Vec::new();
+            file.take(PREFIX_LEN).read_to_end(&mut prefix).ok()?;
+            let mut hasher = DefaultHasher::new();
+            prefix.hash(&mut hasher);
+            Some((len, hasher.finish()))
+        })
+        .collect();
+
+    let mut groups: Vec<((u64, u64), Vec<&Path>)> = Vec::new();
+    for (song, hash) in songs.iter().zip(hashes) {
+        let Some(hash) = hash else {
+            continue;
+        };
+        match groups.iter_mut().find(|(h, _)| *h == hash) {
+            Some((_, group)) => group.push(song),
+            None => groups.push((hash, vec![song])),
+        }
+    }
+    groups.into_iter().map(|(_, g)| g).collect()
+}
+
+/// Indexes the songs of `index` by the normalized artist and title of their tags. This reads the
+/// tags of every song, which takes a lot longer than indexing the file names.
+pub fn tag_index(index: &MusicIndex, stem: &StemOptions) -> TagIndex {
+    let songs: Vec<&PathBuf> = index.all_songs().collect();
+    let keys: Vec<Option<(OsString, OsString)>> = songs
+        .par_iter()
+        .map(|p| {
+            let metadata = SongMetadata::from(p);
+            tag_key(stem, metadata.artists.first()?, &metadata.title)
+        })
+        .collect();
+
+    let mut tag_index = TagIndex::new();
+    for (song, key) in songs.into_iter().zip(keys) {
+        if let Some(key) = key {
+            tag_index.entry(key).or_default().push(song.clone());
+        }
+    }
+    for songs in tag_index.values_mut() {
+        songs.sort();
+    }
+
+    tag_index
+}
+
+/// Normalizes an artist and title with case folding, and accent folding if enabled.
+fn tag_key(stem: &StemOptions, artist: &str, title: &str) -> Option<(OsString, OsString)> {
+    let (artist, title) = (artist.trim(), title.trim());
+    if artist.is_empty() || title.is_empty() {
+        return None;
+    }
+
+    let options = StemOptions {
+        ignore_case: true,
+        ..*stem
+    };
+    Some((options.key(artist.as_ref()), options.key(title.as_ref())))
+}
+
+/// Looks up the song with the artist and title of the entry's `#EXTINF` directive.
+fn match_tags<'index>(
+    tags: &'index TagIndex,
+    options: &MatchOptions,
+    entry: &Entry,
+) -> Option<&'index Path> {
+    let extinf = entry.extinf.as_ref()?;
+    let key = tag_key(
+        &options.stem,
+        extinf.artist.as_ref()?,
+        extinf.title.as_ref()?,
+    )?;
+    let songs = tags.get(&key)?;
+
+    // prefer a song with the same extension
+    let extension = entry.path.extension();
+    let song = songs.iter().find(|s| s.extension() == extension);
+    song.or(songs.first()).map(PathBuf::as_path)
+}
+
+/// Finds the songs of the most similar stem in the `index`, if its normalized levenshtein
+/// similarity is at least `threshold`.
+fn fuzzy_lookup<'index>(
+    index: &'index MusicIndex,
+    key: &OsStr,
+    threshold: f64,
+) -> Option<(&'index [PathBuf], f64)> {
+    let key = key.to_str()?;
+
+    let mut best: Option<(&OsString, &[PathBuf], f64)> = None;
+    for (k, songs) in index.songs.iter() {
+        let Some(s) = k.to_str() else {
+            continue;
+        };
+        let similarity = strsim::normalized_levenshtein(key, s);
+        if similarity < threshold {
+            continue;
+        }
+
+        // break ties by the key, so the result doesn't depend on the hash map's order
+        let is_better = match best {
+            None => true,
+            Some((best_key, _, best_similarity)) => {
+                best_similarity < similarity || best_similarity == similarity && k < best_key
+            }
+        };
+        if is_better {
+            best = Some((k, songs.as_slice(), similarity));
+        }
+    }
+
+    best.map(|(_, songs, similarity)| (songs, similarity))
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    path.canonicalize()
+}
+
+#[cfg(target_os = "windows")]
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    let string = path.canonicalize()?.display().to_string();
+
+    Ok(PathBuf::from(string.replace("\\\\?\\", "")))
+}
+
+/// Converts a path written on another platform. A windows drive letter is removed, so
+/// `C:\Music\song.mp3` becomes the absolute path `/Music/song.mp3`. The path is normalized
+/// lexically, see [`normalize_path`].
+#[cfg(not(target_os = "windows"))]
+pub fn platform_path(string: &str) -> PathBuf {
+    let path = string.replace('\\', "/");
+    let path = match path.as_bytes() {
+        [drive, b':', rest @ ..] if drive.is_ascii_alphabetic() && !rest.starts_with(b":") => {
+            &path[2..]
+        }
+        _ => &path,
+    };
+    normalize_path(Path::new(path))
+}
+
+#[cfg(target_os = "windows")]
+pub fn platform_path(string: &str) -> PathBuf {
+    let path = string.replace('/', "\\");
+    normalize_path(Path::new(&path))
+}
+
+/// Removes `.` components, repeated and trailing separators, and resolves `..` components
+/// against the component before them, without accessing the file system. `..` components at the
+/// start of a relative path are kept.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => (),
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // `..` of the root is the root itself
+                Some(Component::RootDir | Component::Prefix(_)) => (),
+                Some(Component::ParentDir | Component::CurDir) | None => normalized.push(c),
+            },
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m3u_paths(contents: &str) -> Vec<PathBuf> {
+        parse_m3u(contents).into_iter().map(|e| e.path).collect()
+    }
+
+    #[test]
+    fn m3u_quoted_path_with_spaces() {
+        let paths = m3u_paths("\"Artist/path with spaces.mp3\"\n");
+        assert_eq!(paths, [platform_path("Artist/path with spaces.mp3")]);
+    }
+
+    #[test]
+    fn m3u_trailing_whitespace() {
+        let paths = m3u_paths("path.mp3   \r\n  other.mp3\t\rlast.mp3");
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("path.mp3"),
+                PathBuf::from("other.mp3"),
+                PathBuf::from("last.mp3")
+            ]
+        );
+    }
+
+    #[test]
+    fn m3u_lone_quote_is_kept() {
+        assert_eq!(m3u_paths("\"\n"), [PathBuf::from("\"")]);
+    }
+
+    #[test]
+    fn m3u_empty_quoted_path_is_skipped() {
+        let entries = parse_m3u("#EXTINF:1,A - B\n\"\"\nsong.mp3\n");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, Path::new("song.mp3"));
+        assert!(entries[0].extinf.is_some());
+    }
+
+    /// An index of the songs at `paths`, which don't have to exist.
+    fn synthetic_index(paths: &[&str], stem: &StemOptions) -> MusicIndex {
+        let mut songs: HashMap<OsString, Vec<PathBuf>> = HashMap::new();
+        for p in paths {
+            let path = PathBuf::from(p);
+            let key = stem.stem_key(path.file_stem().unwrap());
+            songs.entry(key).or_default().push(path);
+        }
+        songs.values_mut().for_each(|s| s.sort());
+        MusicIndex {
+            songs,
+            playlists: Vec::new(),
+            roots: Vec::new(),
+        }
+    }
+
+    /// The local song every line of the m3u `contents` is matched to.
+    fn resolve(index: &MusicIndex, options: &MatchOptions, contents: &str) -> Vec<Option<PathBuf>> {
+        let entries = parse_m3u(contents);
+        let (_, stats) = m3u_playlist(
+            &DefaultMatcher,
+            index,
+            None,
+            None,
+            options,
+            &entries,
+            None,
+            String::new(),
+        );
+        stats
+            .entries
+            .iter()
+            .map(|e| e.resolved.map(Path::to_path_buf))
+            .collect()
+    }
+
+    fn track_prefix_options() -> MatchOptions {
+        MatchOptions {
+            stem: StemOptions {
+                strip_track_prefix: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn track_prefixes_are_stripped() {
+        assert_eq!(strip_track_prefix("01. Song"), "Song");
+        assert_eq!(strip_track_prefix("1 - Song"), "Song");
+        assert_eq!(strip_track_prefix("03_Song"), "Song");
+        assert_eq!(strip_track_prefix("Song"), "Song");
+    }
+
+    #[test]
+    fn track_prefix_keeps_numbers_that_are_the_name() {
+        assert_eq!(strip_track_prefix("1999"), "1999");
+        assert_eq!(strip_track_prefix("1999 - Prince"), "1999 - Prince");
+        assert_eq!(strip_track_prefix("01"), "01");
+    }
+
+    #[test]
+    fn track_prefix_matches_both_ways() {
+        let options = track_prefix_options();
+        let index = synthetic_index(
+            &["/music/A/01 - Song.mp3", "/music/B/1999.mp3"],
+            &options.stem,
+        );
+        assert_eq!(
+            resolve(&index, &options, "A/Song.mp3\n1999.mp3\n"),
+            [
+                Some(PathBuf::from("/music/A/01 - Song.mp3")),
+                Some(PathBuf::from("/music/B/1999.mp3"))
+            ]
+        );
+
+        let index = synthetic_index(&["/music/A/Song.mp3"], &options.stem);
+        assert_eq!(
+            resolve(&index, &options, "A/03_Song.mp3\n"),
+            [Some(PathBuf::from("/music/A/Song.mp3"))]
+        );
+    }
+
+    #[test]
+    fn track_prefix_is_only_stripped_if_enabled() {
+        let options = MatchOptions::default();
+        let index = synthetic_index(&["/music/A/01 - Song.mp3"], &options.stem);
+        assert_eq!(resolve(&index, &options, "A/Song.mp3\n"), [None]);
+    }
+
+    fn utf16_bytes(bom: [u8; 2], s: &str, to_bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        let mut bytes = bom.to_vec();
+        bytes.extend(s.encode_utf16().flat_map(to_bytes));
+        bytes
+    }
+
+    #[test]
+    fn decode_utf16_le_with_bom() {
+        let bytes = utf16_bytes([0xff, 0xfe], "Sóng ♫.mp3\r\n", u16::to_le_bytes);
+        let m3u = Some(OsStr::new("m3u"));
+        assert_eq!(decode_playlist(&bytes, m3u), "Sóng ♫.mp3\r\n");
+    }
+
+    #[test]
+    fn decode_utf16_be_with_bom() {
+        let bytes = utf16_bytes([0xfe, 0xff], "Sóng ♫.mp3\r\n", u16::to_be_bytes);
+        let m3u8 = Some(OsStr::new("m3u8"));
+        assert_eq!(decode_playlist(&bytes, m3u8), "Sóng ♫.mp3\r\n");
+    }
+
+    #[test]
+    fn decode_utf8_with_bom() {
+        let bytes = b"\xef\xbb\xbfS\xc3\xb3ng.mp3\n";
+        let contents = decode_playlist(bytes, Some(OsStr::new("m3u8")));
+        let entries = parse_playlist_as(&contents, PlaylistInputFormat::M3u);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, Path::new("Sóng.mp3"));
+    }
+
+    #[test]
+    fn decode_latin1_fallback() {
+        let bytes = b"S\xf3ng \x80.mp3\n";
+        assert_eq!(
+            decode_playlist(bytes, Some(OsStr::new("m3u"))),
+            "Sóng €.mp3\n"
+        );
+        // m3u8 playlists are always utf-8
+        assert_eq!(
+            decode_playlist(bytes, Some(OsStr::new("m3u8"))),
+            "S\u{fffd}ng \u{fffd}.mp3\n"
+        );
+    }
+
+    fn exclude_options(stems: &[&str], paths: &[&str]) -> MatchOptions {
+        MatchOptions {
+            exclude_stems: stems.iter().map(OsString::from).collect(),
+            exclude_paths: paths.iter().map(|p| Regex::new(p).unwrap()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exclude_by_stem() {
+        let options = exclude_options(&["Intro"], &[]);
+        assert!(options.is_excluded(Path::new("/music/A/Intro.mp3")));
+        assert!(options.is_excluded(Path::new("/music/B/Intro.flac")));
+        assert!(!options.is_excluded(Path::new("/music/A/Intro Part 2.mp3")));
+    }
+
+    #[test]
+    fn exclude_by_path() {
+        let options = exclude_options(&[], &["/Audiobooks/"]);
+        assert!(options.is_excluded(Path::new("/music/Audiobooks/Chapter 1.mp3")));
+        assert!(!options.is_excluded(Path::new("/music/Rock/Chapter 1.mp3")));
+    }
+
+    #[test]
+    fn excluded_songs_are_left_out() {
+        let options = exclude_options(&["Intro"], &["/Audiobooks/"]);
+        let index = synthetic_index(
+            &[
+                "/music/A/Intro.mp3",
+                "/music/A/Song.mp3",
+                "/music/Audiobooks/Chapter.mp3",
+            ],
+            &options.stem,
+        );
+        let m3u = "A/Intro.mp3\nA/Song.mp3\nAudiobooks/Chapter.mp3\n";
+        assert_eq!(
+            resolve(&index, &options, m3u),
+            [Some(PathBuf::from("/music/A/Song.mp3"))]
+        );
+    }
+
+    /// The straightforward version of `match_file` without fuzzy matching, which normalizes both
+    /// sides of every comparison for each candidate and ranks all of them at once.
+    fn reference_match<'a>(
+        index: &'a MusicIndex,
+        options: &MatchOptions,
+        entry: &Entry,
+    ) -> Option<(&'a Path, usize, bool, bool)> {
+        let stem = &options.stem;
+        let file_path = entry.path.as_path();
+        let file_extension = file_path.extension()?;
+        let songs = index.lookup(&stem.stem_key(file_path.file_stem()?))?;
+        let artist = (entry.extinf.as_ref())
+            .and_then(|e| e.artist.as_deref())
+            .filter(|_| options.prefer_artist_folder);
+        let ranked = songs.iter().map(|local| {
+            let matching = (local.components().rev().skip(1))
+                .zip(file_path.components().rev().skip(1))
+                .take_while(|(l, f)| stem.matches(l.as_os_str(), f.as_os_str()))
+                .count();
+            let artist_folder = artist.is_some_and(|a| {
+                let mut dirs = local.ancestors().skip(1).take(2);
+                dirs.any(|d| d.file_name().is_some_and(|n| stem.matches(n, a.as_ref())))
+            });
+            let local_extension = local.extension().unwrap_or_default();
+            let extension_matches = stem.matches(local_extension, file_extension);
+            let local_parents = local.components().count() - 1 - index.root_len(local);
+            let parent_matches = local_parents > 0 && matching >= local_parents;
+            let rank = (
+                options.extension_priority && extension_matches,
+                matching + artist_folder as usize,
+                extension_preference(&options.prefer_extensions, local_extension),
+                extension_matches,
+                parent_matches,
+                std::cmp::Reverse(local.as_path()),
+            );
+            let result = (local.as_path(), matching, artist_folder, parent_matches);
+            (rank, result)
+        });
+        ranked.max_by(|a, b| a.0.cmp(&b.0)).map(|(_, r)| r)
+    }
+
+    #[test]
+    fn match_file_equals_reference() {
+        let artists = ["Artist", "Ártist", "Other"];
+        let albums = ["Album", "Best Of"];
+        let stems = ["Song", "song", "Sóng", "01 - Song"];
+        let mut paths = Vec::new();
+        for artist in artists {
+            for album in albums {
+                for stem in stems {
+                    for extension in ["mp3", "flac"] {
+                        paths.push(format!("/music/{artist}/{album}/{stem}.{extension}"));
+                    }
+                }
+            }
+        }
+        paths.push("/music/Song.mp3".into());
+        paths.push("/backup/Other/Album/Song.mp3".into());
+        paths.push("/Song.ogg".into());
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+
+        let mut entries = Vec::new();
+        for artist in artists.iter().chain(&["Unknown"]) {
+            for album in albums.iter().chain(&["Missing"]) {
+                for stem in stems {
+                    for extension in ["mp3", "ogg"] {
+                        for path in [
+                            format!("/other/{artist}/{album}/{stem}.{extension}"),
+                            format!("{artist}/{album}/{stem}.{extension}"),
+                            format!("{album}/{stem}.{extension}"),
+                            format!("{stem}.{extension}"),
+                        ] {
+                            entries.push(Entry {
+                                path: PathBuf::from(path),
+                                extinf: Some(ExtInf {
+                                    artist: Some(artist.to_string()),
+                                    ..Default::default()
+                                }),
+                                comments: Vec::new(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut matched = 0;
+        for flags in 0..8 {
+            let options = MatchOptions {
+                stem: StemOptions {
+                    fold_accents: flags & 1 != 0,
+                    ignore_case: flags & 2 != 0,
+                    strip_track_prefix: flags & 4 != 0,
+                },
+                // not a separate flag, which would double the run time of the test
+                extension_priority: flags % 3 == 0,
+                prefer_artist_folder: true,
+                prefer_extensions: vec!["flac".into()],
+                ..Default::default()
+            };
+            let mut index = synthetic_index(&paths, &options.stem);
+            index.roots = vec![PathBuf::from("/music"), PathBuf::from("/backup")];
+            for entry in &entries {
+                let actual = match_file(&index, &options, entry).map(|m| {
+                    let matching = m.matching_components as usize;
+                    (m.path, matching, m.artist_folder, m.parent_matches)
+                });
+                let expected = reference_match(&index, &options, entry);
+                assert_eq!(
+                    actual,
+                    expected,
+                    "{} with {options:?}",
+                    entry.path.display()
+                );
+                matched += actual.is_some() as usize;
+            }
+        }
+        assert!(matched > entries.len());
+    }
+
+    #[test]
+    fn m3u8_with_bom_is_indexed_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let playlist = dir.path().join("list.m3u8");
+        std::fs::write(&playlist, "\u{feff}#EXTM3U\nSóng.mp3\n").unwrap();
+        let bom_path = dir.path().join("bom.m3u8");
+        std::fs::write(&bom_path, "\u{feff}First.mp3\nSecond.mp3\n").unwrap();
+
+        let music_index = index(&[dir.path().to_path_buf()], &IndexOptions::default()).unwrap();
+        let mut playlists = music_index.playlists.clone();
+        playlists.sort();
+        assert_eq!(playlists, [bom_path.clone(), playlist.clone()]);
+
+        let format = PlaylistInputFormat::from_extension(bom_path.extension());
+        let paths: Vec<PathBuf> = (parse_entries(&bom_path, format).into_iter())
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(paths, [Path::new("First.mp3"), Path::new("Second.mp3")]);
+        let entries = parse_entries(&playlist, format);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, Path::new("Sóng.mp3"));
+    }
+
+    #[test]
+    fn dedup_differently_spelled_entries() {
+        let options = MatchOptions {
+            stem: StemOptions {
+                ignore_case: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let index = synthetic_index(&["/music/A/Song.mp3", "/music/B/Other.mp3"], &options.stem);
+        let entries = parse_m3u("A/Song.mp3\nB/Other.mp3\n../music/a/song.MP3\nSong.mp3\n");
+        let (mut playlist, _) = m3u_playlist(
+            &DefaultMatcher,
+            &index,
+            None,
+            None,
+            &options,
+            &entries,
+            None,
+            String::new(),
+        );
+        assert_eq!(playlist.len(), 4);
+
+        assert_eq!(playlist.dedup(), 2);
+        assert_eq!(
+            playlist.songs(),
+            [
+                Path::new("/music/A/Song.mp3"),
+                Path::new("/music/B/Other.mp3")
+            ]
+        );
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn windows_absolute_path_on_unix() {
+        let path = platform_path("C:\\Users\\Me\\Music\\Artist\\Song.mp3");
+        assert_eq!(path, Path::new("/Users/Me/Music/Artist/Song.mp3"));
+        assert_eq!(path.file_stem().unwrap(), "Song");
+        assert_eq!(
+            platform_path("d:/Music/Song.mp3"),
+            Path::new("/Music/Song.mp3")
+        );
+        assert_eq!(
+            platform_path("/home/me/Music/Song.mp3"),
+            Path::new("/home/me/Music/Song.mp3")
+        );
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn posix_absolute_path_on_windows() {
+        let path = platform_path("/home/me/Music/Artist/Song.mp3");
+        assert_eq!(path, Path::new("\\home\\me\\Music\\Artist\\Song.mp3"));
+        assert_eq!(path.file_stem().unwrap(), "Song");
+        assert_eq!(
+            platform_path("C:\\Music\\Song.mp3"),
+            Path::new("C:\\Music\\Song.mp3")
+        );
+    }
+
+    #[test]
+    fn absolute_entries_match_like_relative_ones() {
+        let options = MatchOptions::default();
+        let index = synthetic_index(
+            &["/music/Artist/Song.mp3", "/music/Other/Song.mp3"],
+            &options.stem,
+        );
+        let m3u =
+            "C:\\Users\\Me\\Music\\Artist\\Song.mp3\n/home/me/Artist/Song.mp3\nArtist/Song.mp3\n";
+        let song = Some(PathBuf::from("/music/Artist/Song.mp3"));
+        assert_eq!(
+            resolve(&index, &options, m3u),
+            [song.clone(), song.clone(), song]
+        );
+    }
+
+    /// A music index with the songs of a single stem in the given order, unlike
+    /// [`synthetic_index`] which sorts them.
+    fn unsorted_index(paths: &[&str]) -> MusicIndex {
+        let stem = Path::new(paths[0]).file_stem().unwrap();
+        MusicIndex {
+            songs: HashMap::from([(stem.into(), paths.iter().map(PathBuf::from).collect())]),
+            playlists: Vec::new(),
+            roots: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn three_way_tie_is_broken_by_the_path() {
+        let paths = [
+            "/c/Greatest Hits/Song.mp3",
+            "/a/Greatest Hits/Song.mp3",
+            "/b/Greatest Hits/Song.mp3",
+        ];
+        let options = MatchOptions::default();
+        for rotation in 0..paths.len() {
+            let mut paths = paths;
+            paths.rotate_left(rotation);
+            let index = unsorted_index(&paths);
+            assert_eq!(
+                resolve(&index, &options, "Greatest Hits/Song.mp3\n"),
+                [Some(PathBuf::from("/a/Greatest Hits/Song.mp3"))]
+            );
+        }
+    }
+
+    #[test]
+    fn three_way_tie_is_broken_by_the_parent_path() {
+        let paths = [
+            "/music/x/flac/Greatest Hits/Song.mp3",
+            "/music/flac/Greatest Hits/Song.mp3",
+            "/music/a/flac/Greatest Hits/Song.mp3",
+        ];
+        let options = MatchOptions::default();
+        for rotation in 0..paths.len() {
+            let mut paths = paths;
+            paths.rotate_left(rotation);
+            let mut index = unsorted_index(&paths);
+            index.roots = vec![PathBuf::from("/music")];
+            for entry in [
+                "flac/Greatest Hits/Song.mp3",
+                "/home/other/Music/flac/Greatest Hits/Song.mp3",
+            ] {
+                assert_eq!(
+                    resolve(&index, &options, entry),
+                    [Some(PathBuf::from("/music/flac/Greatest Hits/Song.mp3"))],
+                    "{entry}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parent_path_is_compared_below_the_deepest_music_dir() {
+        let entry = &parse_m3u("Other/flac/Greatest Hits/Song.mp3\n")[0];
+        let parent_matches = |path: &str| {
+            let mut index = unsorted_index(&[path]);
+            index.roots = vec![PathBuf::from("/music"), PathBuf::from("/music/flac")];
+            match_file(&index, &MatchOptions::default(), entry).map(|m| m.parent_matches)
+        };
+        assert_eq!(
+            parent_matches("/music/flac/Greatest Hits/Song.mp3"),
+            Some(true)
+        );
+        assert_eq!(
+            parent_matches("/music/Other/flac/Greatest Hits/Song.mp3"),
+            Some(true)
+        );
+        assert_eq!(
+            parent_matches("/music/A/flac/Greatest Hits/Song.mp3"),
+            Some(false)
+        );
+        // the path below the music dir only has to be a suffix of the one of the entry
+        assert_eq!(parent_matches("/music/Greatest Hits/Song.mp3"), Some(true));
+        // songs outside of the music dirs have to match with their whole parent path
+        assert_eq!(
+            parent_matches("/elsewhere/Greatest Hits/Song.mp3"),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn tie_is_broken_by_the_extension_before_the_parent_path() {
+        let index = unsorted_index(&[
+            "flac/Greatest Hits/Song.flac",
+            "a/flac/Greatest Hits/Song.mp3",
+        ]);
+        assert_eq!(
+            resolve(
+                &index,
+                &MatchOptions::default(),
+                "flac/Greatest Hits/Song.mp3\n"
+            ),
+            [Some(PathBuf::from("a/flac/Greatest Hits/Song.mp3"))]
+        );
+    }
+
+    fn glob_set(globs: &[&str]) -> GlobSet {
+        let mut builder = globset::GlobSetBuilder::new();
+        for g in globs {
+            builder.add(globset::Glob::new(g).unwrap());
+        }
+        builder.build().unwrap()
+    }
+
+    /// The songs indexed in a new directory with the files at `paths`, relative to it.
+    fn indexed_songs(paths: &[&str], options: &IndexOptions) -> Vec<PathBuf> {
+        let dir = tempfile::tempdir().unwrap();
+        for p in paths {
+            let path = dir.path().join(p);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let root = canonicalize(dir.path()).unwrap();
+        let music_index = index(std::slice::from_ref(&root), options).unwrap();
+        let mut songs: Vec<PathBuf> = (music_index.all_songs())
+            .map(|s| s.strip_prefix(&root).unwrap().to_path_buf())
+            .collect();
+        songs.sort();
+        songs
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let options = IndexOptions {
+            include: glob_set(&["**/FLAC/**"]),
+            exclude: glob_set(&["**/ringtones/**"]),
+            ..Default::default()
+        };
+        let songs = indexed_songs(
+            &[
+                "FLAC/Artist/Song.flac",
+                "FLAC/ringtones/Ring.flac",
+                "MP3/Artist/Song.mp3",
+                "ringtones/Ring.mp3",
+            ],
+            &options,
+        );
+        assert_eq!(songs, [Path::new("FLAC/Artist/Song.flac")]);
+    }
+
+    #[test]
+    fn prefer_extension_in_the_same_dir() {
+        let pair = ["/music/Artist/Song.mp3", "/music/Artist/Song.flac"];
+        let m3u = "Artist/Song.ogg\n";
+        for prefer in [["flac", "mp3"], ["mp3", "flac"]] {
+            let options = MatchOptions {
+                prefer_extensions: prefer.map(String::from).to_vec(),
+                ..Default::default()
+            };
+            let index = synthetic_index(&pair, &options.stem);
+            let song = PathBuf::from(format!("/music/Artist/Song.{}", prefer[0]));
+            assert_eq!(resolve(&index, &options, m3u), [Some(song)]);
+        }
+
+        // without a preference the path decides
+        let options = MatchOptions::default();
+        let index = synthetic_index(&pair, &options.stem);
+        let song = PathBuf::from("/music/Artist/Song.flac");
+        assert_eq!(resolve(&index, &options, m3u), [Some(song)]);
+    }
+
+    #[test]
+    fn preferred_extension_beats_the_source_extension() {
+        let options = MatchOptions {
+            prefer_extensions: vec!["flac".into()],
+            ..Default::default()
+        };
+        let index = synthetic_index(&["/music/A/Song.mp3", "/music/A/Song.flac"], &options.stem);
+        let song = PathBuf::from("/music/A/Song.flac");
+        assert_eq!(resolve(&index, &options, "A/Song.mp3\n"), [Some(song)]);
+    }
+
+    fn components(path: &Path) -> Vec<&OsStr> {
+        path.components().map(|c| c.as_os_str()).collect()
+    }
+
+    #[test]
+    fn normalize_current_dir() {
+        let path = platform_path("./Artist/./Album/Song.mp3");
+        assert_eq!(components(&path), ["Artist", "Album", "Song.mp3"]);
+        assert_eq!(path.file_stem().unwrap(), "Song");
+    }
+
+    #[test]
+    fn normalize_repeated_separators() {
+        let path = platform_path("Artist//Album///Song.mp3/");
+        assert_eq!(components(&path), ["Artist", "Album", "Song.mp3"]);
+        assert_eq!(path.file_stem().unwrap(), "Song");
+    }
+
+    #[test]
+    fn normalize_parent_dir() {
+        let path = platform_path("Artist/Other/../Album/Song.mp3");
+        assert_eq!(components(&path), ["Artist", "Album", "Song.mp3"]);
+        // leading `..` components are kept, and the root has no parent
+        let path = platform_path("../../Album/./Song.mp3");
+        assert_eq!(components(&path), ["..", "..", "Album", "Song.mp3"]);
+        assert_eq!(
+            normalize_path(Path::new("/../Song.mp3")),
+            Path::new("/Song.mp3")
+        );
+    }
+
+    #[test]
+    fn normalized_entries_match() {
+        let options = MatchOptions::default();
+        let index = synthetic_index(
+            &[
+                "/music/Artist/Album/Song.mp3",
+                "/music/Other/Album/Song.mp3",
+            ],
+            &options.stem,
+        );
+        let m3u = "Artist/./Album//Song.mp3\nArtist/Other/../Album/Song.mp3\n";
+        let song = Some(PathBuf::from("/music/Artist/Album/Song.mp3"));
+        assert_eq!(resolve(&index, &options, m3u), [song.clone(), song]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn self_referential_symlinks_are_walked_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        std::fs::create_dir(root.join("Album")).unwrap();
+        std::fs::write(root.join("Album/Song.mp3"), "").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("Album/loop")).unwrap();
+        std::os::unix::fs::symlink(".", root.join("self")).unwrap();
+
+        for follow_symlinks in [false, true] {
+            let options = IndexOptions {
+                follow_symlinks,
+                ..Default::default()
+            };
+            let music_index = index(std::slice::from_ref(&root), &options).unwrap();
+            let songs: Vec<&PathBuf> = music_index.all_songs().collect();
+            assert_eq!(songs, [&root.join("Album/Song.mp3")]);
+        }
+    }
+
+    #[test]
+    fn extension_priority_orderings() {
+        let paths = ["/music/Artist/Album/Song.mp3", "/music/Singles/Song.flac"];
+        let m3u = "Artist/Album/Song.flac\n";
+
+        let options = MatchOptions::default();
+        let index = synthetic_index(&paths, &options.stem);
+        let song = PathBuf::from("/music/Artist/Album/Song.mp3");
+        assert_eq!(resolve(&index, &options, m3u), [Some(song)]);
+
+        let options = MatchOptions {
+            extension_priority: true,
+            ..Default::default()
+        };
+        let song = PathBuf::from("/music/Singles/Song.flac");
+        assert_eq!(resolve(&index, &options, m3u), [Some(song)]);
+    }
+
+    #[test]
+    fn verify_exists_skips_deleted_songs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        for p in ["Artist/Album/Song.mp3", "Other/Song.mp3"] {
+            std::fs::create_dir_all(root.join(p).parent().unwrap()).unwrap();
+            std::fs::write(root.join(p), "").unwrap();
+        }
+        let music_index = index(std::slice::from_ref(&root), &IndexOptions::default()).unwrap();
+        // replaced by a directory of the same name after indexing
+        std::fs::remove_file(root.join("Artist/Album/Song.mp3")).unwrap();
+        std::fs::create_dir(root.join("Artist/Album/Song.mp3")).unwrap();
+
+        let m3u = "Artist/Album/Song.mp3\n";
+        let stale = root.join("Artist/Album/Song.mp3");
+        let options = MatchOptions::default();
+        assert_eq!(resolve(&music_index, &options, m3u), [Some(stale)]);
+
+        let options = MatchOptions {
+            verify_exists: true,
+            ..Default::default()
+        };
+        let song = root.join("Other/Song.mp3");
+        assert_eq!(resolve(&music_index, &options, m3u), [Some(song)]);
+
+        std::fs::remove_file(root.join("Other/Song.mp3")).unwrap();
+        assert_eq!(resolve(&music_index, &options, m3u), [None]);
+    }
+
+    #[test]
+    fn confidence_scores() {
+        let index = synthetic_index(&["/music/Artist/Album/Song.mp3"], &StemOptions::default());
+        let confidence = |path: &str| {
+            let entry = &parse_m3u(path)[0];
+            match_file(&index, &MatchOptions::default(), entry).map(|m| m.confidence())
+        };
+        assert_eq!(confidence("Artist/Album/Song.mp3"), Some(40 + 30 + 15));
+        assert_eq!(confidence("Other/Album/Song.mp3"), Some(40 + 15 + 15));
+        // a blind stem hit without any matching directory or extension
+        assert_eq!(confidence("Song.flac"), Some(40));
+    }
+
+    #[test]
+    fn entries_record_the_confidence_of_their_match() {
+        let index = synthetic_index(&["/music/Artist/Album/Song.mp3"], &StemOptions::default());
+        let entries = parse_m3u("Artist/Album/Song.mp3\nOther/Album/Song.flac\nMissing.mp3\n");
+        let (_, stats) = m3u_playlist(
+            &DefaultMatcher,
+            &index,
+            None,
+            None,
+            &MatchOptions::default(),
+            &entries,
+            None,
+            String::new(),
+        );
+        let confidences: Vec<_> = stats.entries.iter().map(|e| e.confidence).collect();
+        assert_eq!(confidences, [Some(40 + 30 + 15), Some(40 + 15), None]);
+    }
+
+    #[test]
+    fn min_confidence_rejects_matches() {
+        let options = MatchOptions {
+            min_confidence: 50,
+            ..Default::default()
+        };
+        let index = synthetic_index(&["/music/Artist/Song.mp3"], &options.stem);
+        let entries = parse_m3u("Artist/Song.mp3\nSong.flac\n");
+        let (playlist, stats) = m3u_playlist(
+            &DefaultMatcher,
+            &index,
+            None,
+            None,
+            &options,
+            &entries,
+            None,
+            String::new(),
+        );
+        assert_eq!(playlist.songs(), [Path::new("/music/Artist/Song.mp3")]);
+        assert_eq!(playlist.unmatched(), [Path::new("Song.flac")]);
+        assert_eq!(stats.low_confidence, 1);
+    }
+}
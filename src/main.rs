@@ -1,26 +1,53 @@
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ffi::{OsStr, OsString};
-use std::io;
+use std::io::{IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::exit;
+use std::process::ExitCode;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use clap::{crate_authors, crate_version, value_parser, Arg, ColorChoice, Command, ValueHint};
+use clap::{
+    crate_authors, crate_version, value_parser, Arg, ArgAction, ArgGroup, ArgMatches, ColorChoice,
+    Command, ValueHint,
+};
 use clap_complete::generate;
 use clap_complete::shells::{Bash, Elvish, Fish, PowerShell, Zsh};
-use walkdir::WalkDir;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use indicatif_log_bridge::LogWrapper;
+use log::{debug, error, info, warn, Level, LevelFilter};
+use playlist_localizer::export::{copy_file, link_file, Export, Exported};
+use playlist_localizer::itunes::itunes_playlists;
+use playlist_localizer::metadata::{MetadataCache, ARTIST_SEPARATOR, CACHE_FILE_NAME};
+use playlist_localizer::playlist::{
+    write_index, CsvColumn, FormatOptions, LineEnding, Overwrite, Playlist, PlaylistFormat,
+    SortOrder, EXTINF_TEMPLATE,
+};
+use playlist_localizer::rekordbox::rekordbox_playlists;
+use playlist_localizer::{
+    canonicalize, content_groups, decode_playlist, duplicate_stems, expand_nested,
+    index_with_progress, is_hls, m3u_playlist, m3u_playlist_paths, normalize_path, parse_entries,
+    parse_playlist_as, read_playlist, skip_nested, song_folders, tag_index, zip_playlists,
+    DefaultMatcher, Entry, Extensions, IndexOptions, MatchOptions, MusicIndex, PlaylistInputFormat,
+    StemOptions, SymlinkCollapse, PLAYLIST_EXTENSIONS,
+};
+use rayon::prelude::*;
+use regex::Regex;
 
-use crate::playlist::{Playlist, PlaylistFormat};
+use crate::config::{Config, Profile, PROFILES};
+use crate::error::AppError;
+use crate::journal::Journal;
 
-mod metadata;
-mod playlist;
+mod config;
+mod diff;
+mod error;
+mod journal;
+mod report;
+mod watch;
 
 const BIN_NAME: &str = "playlist-localizer";
 
-const MUSIC_EXTENSIONS: [&str; 7] = ["aac", "flac", "m4a", "m4b", "mp3", "ogg", "opus"];
-const PLAYLIST_EXTENSIONS: [&str; 1] = ["m3u"];
-
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum Shell {
     Bash,
@@ -45,7 +72,27 @@ impl FromStr for Shell {
     }
 }
 
-fn main() {
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run() -> Result<(), AppError> {
+    // the config is needed for the defaults of the arguments, so it's loaded before they're parsed
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let config = match Config::load(&args) {
+        Ok(c) => c,
+        Err(e) => {
+            init_logger(LevelFilter::Info, &MultiProgress::new());
+            return Err(e);
+        }
+    };
+
     let mut app = Command::new("playlist localizer")
         .color(ColorChoice::Auto)
         .version(crate_version!())
@@ -55,9 +102,10 @@ fn main() {
             Arg::new("music-dir")
                 .short('m')
                 .long("music-dir")
-                .help("The directory which will be searched for playlists and music files")
+                .help("The directory which will be searched for playlists and music files, can be passed multiple times")
                 .num_args(1)
-                .required_unless_present("generate-completion")
+                .action(ArgAction::Append)
+                .value_parser(expand_path)
                 .conflicts_with("generate-completion")
                 .value_hint(ValueHint::DirPath),
         )
@@ -67,7 +115,7 @@ fn main() {
                 .long("output-dir")
                 .help("The output directory which files will be written to")
                 .num_args(1)
-                .required_unless_present("generate-completion")
+                .value_parser(expand_path)
                 .conflicts_with("generate-completion")
                 .value_hint(ValueHint::DirPath),
         )
@@ -75,18 +123,20 @@ fn main() {
             Arg::new("format")
                 .short('f')
                 .long("format")
-                .help("The wanted output format")
-                .num_args(0..1)
+                .help("The wanted output format, one of m3u, extm3u, pls, xspf, wpl, json, csv or cue, a cue sheet writes every song as a track of its own file. A comma separated list or 'all' writes every playlist once in each format, with the conventional extension of the format. A file named like the playlist with the extension .fmt, containing a format, overrides it for that playlist")
+                .num_args(1)
                 .default_value("m3u")
-                .value_parser(value_parser!(PlaylistFormat)),
+                .value_parser(playlist_formats),
         )
         .arg(
             Arg::new("output-file-extension")
                 .short('e')
                 .long("output-file-extension")
                 .value_name("extension")
-                .help("The file extension of the output playlist files")
-                .num_args(1),
+                .help("The file extension of the output playlist files, a leading dot is removed, defaults to the conventional extension of the format. With multiple formats a comma separated list gives each format the extension at the same position")
+                .num_args(1)
+                .value_delimiter(',')
+                .value_parser(output_extension),
         )
         .arg(
             Arg::new("generate-completion")
@@ -97,218 +147,2170 @@ fn main() {
                 .conflicts_with("music-dir")
                 .num_args(1)
                 .value_parser(value_parser!(Shell)),
-        );
-
-    let matches = app.clone().get_matches();
-
-    let generate_completion = matches.get_one("generate-completion");
-
-    if let Some(shell) = generate_completion {
-        let mut stdout = std::io::stdout();
-        match shell {
-            Shell::Bash => generate(Bash, &mut app, BIN_NAME, &mut stdout),
-            Shell::Elvish => generate(Elvish, &mut app, BIN_NAME, &mut stdout),
-            Shell::Fish => generate(Fish, &mut app, BIN_NAME, &mut stdout),
-            Shell::Zsh => generate(Zsh, &mut app, BIN_NAME, &mut stdout),
-            Shell::Pwrsh => generate(PowerShell, &mut app, BIN_NAME, &mut stdout),
-        }
-        exit(0);
-    }
-
-    let music_dir = matches.get_one::<String>("music-dir").unwrap();
-    let output_dir = matches.get_one::<String>("output-dir").unwrap();
-    let format = matches.get_one("format").copied().unwrap();
-    let extension = matches.get_one("output-file-extension").unwrap_or(&"");
-
-    println!("indexing...");
-    let (music_index, playlist_index) = index(music_dir.as_ref());
-
-    println!("localizing songs...");
-    let playlists: Vec<Playlist> = playlist_index
-        .iter()
-        .filter_map(|p| {
-            let file_paths = m3u_playlist_paths(p);
-            let name = p.file_stem().and_then(|s| s.to_str());
-
-            name.map(|s| m3u_playlist(&music_index, &file_paths, s.to_string()))
-        })
-        .collect();
-
-    println!("writing playlists...");
-    for mut p in playlists {
-        p.write_to(output_dir.as_ref(), format, extension);
-    }
-
-    println!("done");
-}
-
-fn index(music_dir: &Path) -> (HashMap<OsString, Vec<PathBuf>>, Vec<PathBuf>) {
-    let abs_music_path = match canonicalize(music_dir) {
-        Ok(t) => t,
-        Err(e) => {
-            println!(
-                "Not a valid music dir path: {}\n{:?}",
-                music_dir.display(),
-                e
-            );
-            exit(1)
-        }
-    };
-    let mut music_index = HashMap::<OsString, Vec<PathBuf>>::new();
-    let mut playlist_index = Vec::new();
-
-    let iter = WalkDir::new(abs_music_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| match e.metadata() {
-            Ok(m) => m.is_file(),
-            Err(_e) => false,
-        });
-    for d in iter {
-        let path = d.into_path();
-        if let Some(extension) = path.extension() {
-            let Some(file_stem) = path.file_stem() else {
-                continue;
-            };
-            match Extension::of(extension) {
-                Extension::Music => match music_index.entry(file_stem.to_owned()) {
-                    Entry::Occupied(songs) => {
-                        songs.into_mut().push(path);
-                    }
-                    Entry::Vacant(v) => {
-                        v.insert(vec![path]);
-                    }
-                },
-                Extension::Playlist => playlist_index.push(path),
-                Extension::Unknown => (),
-            }
-        }
-    }
-
-    (music_index, playlist_index)
-}
-
-fn m3u_playlist_paths(playlist_path: &Path) -> Vec<PathBuf> {
-    let mut results: Vec<PathBuf> = Vec::new();
-    if let Ok(contents) = std::fs::read_to_string(playlist_path) {
-        for l in contents.lines() {
-            if !l.starts_with("#EXT") {
-                results.push(platform_path(l));
-            }
-        }
-    }
-
-    results
-}
-
-fn m3u_playlist<'a>(
-    index: &'a HashMap<OsString, Vec<PathBuf>>,
-    file_paths: &[PathBuf],
-    name: String,
-) -> Playlist<'a> {
-    let songs = file_paths
-        .iter()
-        .filter_map(|p| match_file(index, p))
-        .collect();
-
-    Playlist::new(name, songs)
-}
-
-#[derive(Clone, Copy, Debug)]
-enum Extension {
-    Music,
-    Playlist,
-    Unknown,
-}
-
-impl Extension {
-    #[inline]
-    fn of(s: &OsStr) -> Extension {
-        for e in MUSIC_EXTENSIONS.iter() {
-            if s == *e {
-                return Extension::Music;
-            }
-        }
-        for e in PLAYLIST_EXTENSIONS.iter() {
-            if s == *e {
-                return Extension::Playlist;
-            }
-        }
-
-        Extension::Unknown
-    }
-}
-
-#[derive(Debug, Default, Clone)]
-struct FileMatch<'a> {
-    extension_matches: bool,
-    matching_components: usize,
-    path: Option<&'a Path>,
-}
-
-#[inline]
-fn match_file<'index>(
-    index: &'index HashMap<OsString, Vec<PathBuf>>,
-    file_path: &Path,
-) -> Option<&'index Path> {
-    let file_stem = file_path.file_stem()?;
-    let local_songs = index.get(file_stem)?;
-
-    let mut best_match = FileMatch::default();
-    for local_path in local_songs.iter() {
-        let (Some(local_extension), Some(file_extension)) =
-            (local_path.extension(), file_path.extension())
-        else {
-            continue;
-        };
-
-        let mut file_match = FileMatch {
-            path: Some(local_path),
-            ..Default::default()
-        };
-        let local_components = local_path.components().rev().skip(1);
-        let file_components = file_path.components().rev().skip(1);
-        for (i, (local_comp, file_comp)) in local_components.zip(file_components).enumerate() {
-            if local_comp != file_comp {
-                file_match.matching_components = i;
-                break;
-            }
-        }
-
-        file_match.extension_matches = file_extension == local_extension;
-
-        if best_match.matching_components < file_match.matching_components
-            || best_match.matching_components == file_match.matching_components
-                && !best_match.extension_matches
-                && file_match.extension_matches
-        {
-            best_match = file_match;
-        }
-    }
-
-    best_match.path
-}
-
-#[cfg(not(target_os = "windows"))]
-fn canonicalize(path: &Path) -> io::Result<PathBuf> {
-    path.canonicalize()
-}
-
-#[cfg(target_os = "windows")]
-fn canonicalize(path: &Path) -> io::Result<PathBuf> {
-    let string = path.canonicalize()?.display().to_string();
-
-    Ok(PathBuf::from(string.replace("\\\\?\\", "")))
-}
-
-#[cfg(not(target_os = "windows"))]
-fn platform_path(string: &str) -> PathBuf {
-    let path = string.replace('\\', "/");
-    PathBuf::from(path)
-}
-
-#[cfg(target_os = "windows")]
-fn platform_path(string: &str) -> PathBuf {
-    let path = string.replace('/', "\\");
-    PathBuf::from(path)
+        )
+        .arg(
+            Arg::new("deterministic")
+                .long("deterministic")
+                .visible_alias("single-thread")
+                .help("Process everything sequentially, the results are sorted either way. This trades speed for easier debugging")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .help("Overwrite existing output files without asking for confirmation")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("confirm-threshold")
+                .long("confirm-threshold")
+                .value_name("count")
+                .help("Ask for confirmation when more than this many existing output files would be overwritten")
+                .num_args(1)
+                .default_value("10")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .help("Fail instead of proceeding when a confirmation would be required but stdin or stdout is not a terminal or the output dir is inside a music or playlist dir, and exit with code 2 if any song wasn't found")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("fold-accents")
+                .long("fold-accents")
+                .help("Ignore diacritics when comparing file names, so 'Björk' matches 'Bjork'")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("write-index")
+                .long("write-index")
+                .value_name("name")
+                .help("Write an additional playlist with the given name referencing all generated playlists")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("fallback-dir")
+                .long("fallback-dir")
+                .help("A directory which will be searched for songs that weren't found in the music dir")
+                .num_args(1)
+                .value_hint(ValueHint::DirPath),
+        )
+        .arg(
+            Arg::new("skip-hidden")
+                .long("skip-hidden")
+                .help("Don't index files and directories whose name starts with a dot")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ignore-dir")
+                .long("ignore-dir")
+                .value_name("name")
+                .help("Don't index directories with this name, like '@eaDir', can be passed multiple times")
+                .num_args(1)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("index-depth")
+                .long("index-depth")
+                .value_name("depth")
+                .help("How deep the music and playlist dirs are searched, 1 only searches the files directly inside them")
+                .num_args(1)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("no-canonicalize")
+                .long("no-canonicalize")
+                .help("Use the music and playlist dirs as they were passed, made absolute, instead of resolving symlinks in them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sniff")
+                .long("sniff")
+                .help("Index files without an extension as music if their first bytes look like mp3, flac, ogg or mp4 audio, this reads the start of every such file")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("include")
+                .long("include")
+                .value_name("glob")
+                .help("Only index music files whose absolute path matches this glob, like '**/FLAC/**', can be passed multiple times")
+                .num_args(1)
+                .value_parser(Glob::new)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .value_name("glob")
+                .help("Don't index files and directories whose absolute path matches this glob, takes precedence over --include, can be passed multiple times")
+                .num_args(1)
+                .value_parser(Glob::new)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("warn-duplicates")
+                .long("warn-duplicates")
+                .help("Warn about songs with the same name in different directories of the music dirs")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("hash-duplicates")
+                .long("hash-duplicates")
+                .help("Compare the start of the songs reported by --warn-duplicates to find ones with the same content")
+                .requires("warn-duplicates")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("playlist-dir")
+                .long("playlist-dir")
+                .help("The directory which will be searched for playlists instead of the music dir")
+                .num_args(1)
+                .value_parser(expand_path)
+                .value_hint(ValueHint::DirPath),
+        )
+        .arg(
+            Arg::new("base-dir")
+                .long("base-dir")
+                .value_name("dir")
+                .help("Resolve the relative entries of all source playlists from this directory instead of the directory of each playlist")
+                .num_args(1)
+                .value_parser(absolute_path)
+                .value_hint(ValueHint::DirPath),
+        )
+        .arg(
+            Arg::new("extinf-template")
+                .long("extinf-template")
+                .value_name("template")
+                .help("The information written after '#EXTINF:' in the extm3u format, supports the placeholders <duration>, <duration_hms>, <artist>, <title>, <album>, <track> and <path>")
+                .num_args(1)
+                .default_value(EXTINF_TEMPLATE),
+        )
+        .arg(
+            Arg::new("artist-separator")
+                .long("artist-separator")
+                .value_name("separator")
+                .help("The separator between multiple artists of a song")
+                .num_args(1)
+                .default_value(ARTIST_SEPARATOR),
+        )
+        .arg(
+            Arg::new("prefer-file-tags")
+                .long("prefer-file-tags")
+                .help("Write the tags of the songs in the extm3u format, instead of the '#EXTINF' lines of the source playlists")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("preserve-comments")
+                .long("preserve-comments")
+                .help("Keep the comments and directives like '#EXTGRP' of the source playlists in the extm3u format, and mark songs that weren't found with a comment")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("flatten-output")
+                .long("flatten-output")
+                .help("Write only the file names of songs, for players that ignore directories, like some car stereos")
+                .conflicts_with("prepend-prefix")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("keep-missing")
+                .long("keep-missing")
+                .help("Write a '# MISSING: <path>' comment with the source path of each song that wasn't found at its position, in the m3u and extm3u formats, to fix them by hand later")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("match-tags")
+                .long("match-tags")
+                .help("Look up songs that weren't found by the artist and title of their '#EXTINF' line, this reads the tags of every song in the music dir which makes indexing a lot slower")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-depth")
+                .long("max-depth")
+                .value_name("depth")
+                .help("How deeply playlists included in other playlists are expanded")
+                .num_args(1)
+                .default_value("8")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("keep-urls")
+                .long("keep-urls")
+                .help("Write http and https URLs of the source playlists to the m3u and extm3u formats instead of skipping them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("preserve-tree")
+                .long("preserve-tree")
+                .help("Mirror the directories of the playlists relative to the music or playlist dir inside the output dir")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("line-ending")
+                .long("line-ending")
+                .help("The line separator of the output playlists, 'lf' or 'crlf'")
+                .num_args(1)
+                .default_value(LineEnding::default().name())
+                .value_parser(value_parser!(LineEnding)),
+        )
+        .arg(
+            Arg::new("lossy-paths")
+                .long("lossy-paths")
+                .help("Write song paths that aren't valid unicode with replacement characters instead of skipping them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .value_name("order")
+                .help("Sort the songs of the output playlists, 'none', 'path', 'title' or 'artist'")
+                .num_args(1)
+                .default_value("none")
+                .value_parser(value_parser!(SortOrder)),
+        )
+        .arg(
+            Arg::new("merge")
+                .long("merge")
+                .value_name("name")
+                .help("Write the songs of all playlists into a single playlist with this name")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("output-name-template")
+                .long("output-name-template")
+                .value_name("template")
+                .help("The file name of the written playlists without the extension, supports the placeholders <name> and <date>, the current UTC date as YYYY-MM-DD")
+                .num_args(1)
+                .default_value("<name>"),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .help("Keep running and localize the playlists again whenever one of them is created, changed or removed, until interrupted with ctrl-c. The music dirs are indexed again for every run")
+                .conflicts_with_all(["in-place", "stdout", "from-stdin", "dry-run"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("self-test")
+                .long("self-test")
+                .help("Read the written playlists back and check that every song path in them exists, taking the path transformations into account. Exits with an error if one doesn't. xspf, json and csv playlists aren't checked")
+                .conflicts_with_all(["stdout", "dry-run", "flatten-output"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("copy-hls")
+                .long("copy-hls")
+                .help("Copy HLS playlists, which contain '#EXT-X-' tags and stream segments instead of songs, to the output dir unchanged instead of skipping them")
+                .conflicts_with("in-place")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("in-place")
+                .long("in-place")
+                .help("Write each playlist into the directory of its source playlist instead of the output directory, see also --output-file-extension")
+                .conflicts_with_all([
+                    "output-dir",
+                    "from-stdin",
+                    "itunes-xml",
+                    "rekordbox-xml",
+                    "playlist-zip",
+                    "folders-as-playlists",
+                    "preserve-tree",
+                    "merge",
+                    "write-index",
+                    "export",
+                ])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-overwrite")
+                .long("no-overwrite")
+                .help("Skip playlists whose output file already exists")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("backup")
+                .long("backup")
+                .help("Rename existing output files by appending '.bak' before overwriting them")
+                .conflicts_with("no-overwrite")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .value_name("count")
+                .help("Only localize the first playlists found, ordered by their path, useful to try options with --dry-run")
+                .num_args(1)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("playlist-glob")
+                .long("playlist-glob")
+                .value_name("glob")
+                .help("Only localize playlists whose file name matches this glob, like 'Workout*.m3u', can be passed multiple times")
+                .num_args(1)
+                .value_parser(Glob::new)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("since")
+                .long("since")
+                .value_name("age")
+                .help("Only localize playlists modified within this time, like 24h or 7d, the units are s, m, h, d and w")
+                .num_args(1)
+                .value_parser(parse_age),
+        )
+        .arg(
+            Arg::new("chunk-size")
+                .long("chunk-size")
+                .value_name("songs")
+                .help("Split playlists with more songs into numbered playlists named '<name>.1', '<name>.2', ...")
+                .num_args(1)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("dedup")
+                .long("dedup")
+                .help("Remove songs that resolve to the same file as a previous song of the playlist")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("number-entries")
+                .long("number-entries")
+                .help("Prepend a '# 0001' style comment to each song of the output playlists")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("music-ext")
+                .long("music-ext")
+                .value_name("extension")
+                .help("Also index music files with this extension, can be passed multiple times or as a comma separated list")
+                .num_args(1)
+                .value_delimiter(',')
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("extension-priority")
+                .long("extension-priority")
+                .help("Prefer songs with the extension of the playlist entry over ones in more matching directories")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("prefer-ext")
+                .long("prefer-ext")
+                .value_name("extension")
+                .help("Prefer songs with these extensions, in the given order, when several match equally well, like 'flac,mp3'")
+                .num_args(1)
+                .value_delimiter(',')
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("exclude-stem")
+                .long("exclude-stem")
+                .value_name("stem")
+                .help("Leave out matched songs with this file stem, like interludes that keep matching the wrong entries, can be passed multiple times")
+                .num_args(1)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("exclude-path-regex")
+                .long("exclude-path-regex")
+                .value_name("regex")
+                .help("Leave out matched songs whose local path matches this regex, can be passed multiple times")
+                .num_args(1)
+                .value_parser(Regex::new)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("playlist-ext")
+                .long("playlist-ext")
+                .value_name("extension")
+                .help("Also read playlists with this extension, asx, pls and wpl files are parsed as such, txt files as one path per line, cue sheets as one entry per track and all others as m3u, can be passed multiple times or as a comma separated list. The tracks of a cue sheet that share a file are written as repeated entries of it with the track's title and duration, how they are played depends on the player")
+                .num_args(1)
+                .value_delimiter(',')
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("skip-music-ext")
+                .long("skip-music-ext")
+                .value_name("extension")
+                .help("Don't index music files with this extension, can be passed multiple times")
+                .num_args(1)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("diff-against")
+                .long("diff-against")
+                .value_name("dir")
+                .help("Print how the localized songs differ from the playlists previously written to this directory, before writing anything")
+                .num_args(1)
+                .value_hint(ValueHint::DirPath),
+        )
+        .arg(
+            Arg::new("follow-symlinks")
+                .long("follow-symlinks")
+                .help("Follow symlinks while indexing, directories that resolve to an already visited directory are skipped to prevent cycles")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("collapse-symlinks")
+                .long("collapse-symlinks")
+                .value_name("keep")
+                .help("Treat songs that resolve to the same file as one, keeping either the first alias or the canonical path")
+                .num_args(0..=1)
+                .default_missing_value("alias")
+                .value_parser(value_parser!(SymlinkCollapse)),
+        )
+        .arg(
+            Arg::new("dedup-index")
+                .long("dedup-index")
+                .help("Treat byte-identical songs with the same name as one, keeping the one with the shortest path. This reads every song that has a duplicate name and the same size")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("journal")
+                .long("journal")
+                .value_name("file")
+                .help("Record each written playlist in this file as soon as it's written")
+                .num_args(1)
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .help("Skip playlists that are already recorded as written in the journal")
+                .requires("journal")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("canonical-output-paths")
+                .long("canonical-output-paths")
+                .help("Resolve symlinks in the song paths that are written, without affecting matching")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("playlist-zip")
+                .long("playlist-zip")
+                .value_name("file")
+                .help("A zip archive containing additional playlists to localize")
+                .num_args(1)
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("itunes-xml")
+                .long("itunes-xml")
+                .value_name("file")
+                .help("Localize the playlists of an iTunes or Music 'Library.xml' instead of the playlists in the music or playlist dir")
+                .num_args(1)
+                .conflicts_with("from-stdin")
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("rekordbox-xml")
+                .long("rekordbox-xml")
+                .value_name("file")
+                .help("Localize the playlists of a Rekordbox 'rekordbox.xml' export instead of the playlists in the music or playlist dir, playlists in folders are named 'folder - name'")
+                .num_args(1)
+                .conflicts_with_all(["from-stdin", "itunes-xml"])
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("folders-as-playlists")
+                .long("folders-as-playlists")
+                .help("Write a playlist for every directory that directly contains songs, named after the directory, instead of localizing playlists")
+                .conflicts_with_all(["from-stdin", "itunes-xml", "rekordbox-xml", "playlist-dir"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("on-empty")
+                .long("on-empty")
+                .value_name("action")
+                .help("What to do with playlists that contain no songs after localization")
+                .num_args(1)
+                .default_value("write")
+                .value_parser(value_parser!(OnEmpty)),
+        )
+        .arg(
+            Arg::new("ignore-case")
+                .short('i')
+                .long("ignore-case")
+                .help("Ignore the case of file names when matching songs, using unicode case folding")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("genre-from-depth")
+                .long("genre-from-depth")
+                .value_name("depth")
+                .help("Use the name of the directory this many levels above a song as its genre if its tags have none, 1 is the directory containing it. The genre is written as #EXTGENRE in extm3u and in the json format")
+                .num_args(1)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("strip-track-prefix")
+                .long("strip-track-prefix")
+                .help("Ignore a leading track number like '01. ', '1 - ' or '03_' in file names when matching songs, the written paths keep it")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("emit-base-comment")
+                .long("emit-base-comment")
+                .help("Write a '# base: <dir>' comment at the top of each playlist, naming the directory its songs are relative to")
+                .requires("relative")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("min-confidence")
+                .long("min-confidence")
+                .value_name("score")
+                .help("Treat songs whose match confidence is below this score as not found. The score ranges from 0 to 100: 40 for an exact file name (30 if it only matches after normalization), 30 scaled by the similarity for fuzzy matches, 15 for each matching directory up to 45, and 15 for a matching extension")
+                .num_args(1)
+                .default_value("0")
+                .value_parser(value_parser!(u8).range(0..=100)),
+        )
+        .arg(
+            Arg::new("min-matching-components")
+                .long("min-matching-components")
+                .value_name("count")
+                .help("Treat songs as not found if fewer of the directories above them match the source entry, counted from the innermost one. Entries without directories in the source playlist never match more than 0")
+                .num_args(1)
+                .default_value("0")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("csv-columns")
+                .long("csv-columns")
+                .value_name("columns")
+                .help("The comma separated columns of the csv format [possible values: path, title, artist, album, duration]")
+                .num_args(1)
+                .value_delimiter(',')
+                .default_value("path,title,artist,duration")
+                .value_parser(value_parser!(CsvColumn)),
+        )
+        .arg(
+            Arg::new("csv-header")
+                .long("csv-header")
+                .help("Write a header row in the csv format")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("art-names")
+                .long("art-names")
+                .value_name("names")
+                .help("Write the first of these files in the directory of each song as its cover art, in the extm3u and xspf formats [default without names: cover.jpg,folder.jpg,front.png]")
+                .num_args(0..=1)
+                .value_delimiter(',')
+                .default_missing_values(["cover.jpg", "folder.jpg", "front.png"]),
+        )
+        .arg(
+            Arg::new("split-embedded-cue")
+                .long("split-embedded-cue")
+                .help("Write an entry like 'album.flac#track=2' for every track of a cue sheet embedded in a flac file, in the m3u and extm3u formats, and a track of the file at its offset in the cue format. Not all players support these track hints")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("json-pretty")
+                .long("json-pretty")
+                .help("Indent the json format over multiple lines, this is the default if stdout is a terminal")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("json-compact")
+                .long("json-compact")
+                .help("Write the json format on a single line, this is the default if stdout isn't a terminal")
+                .conflicts_with("json-pretty")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("prefer-artist-folder")
+                .long("prefer-artist-folder")
+                .help("Prefer songs inside a directory named after the artist from the source playlist's #EXTINF lines")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('j')
+                .long("threads")
+                .help("The maximum number of threads used, defaults to the number of cpus")
+                .num_args(1)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("fuzzy")
+                .long("fuzzy")
+                .value_name("similarity")
+                .help("Match songs whose file name is not found to the most similar one, if the similarity is at least this value between 0 and 1")
+                .num_args(1)
+                .value_parser(parse_ratio),
+        )
+        .arg(
+            Arg::new("verify-exists")
+                .long("verify-exists")
+                .help("Check that matched songs are still regular files, and fall back to the next best match for ones that were deleted since they were indexed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verify-matches")
+                .long("verify-matches")
+                .help("Check the header of matched songs, and leave out ones that don't look like audio files, like empty or corrupt placeholders")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("fuzzy-dirs")
+                .long("fuzzy-dirs")
+                .value_name("similarity")
+                .help("Count directories with a similar name, like 'Greatest Hits' and 'Greatest Hits (Deluxe)', as a partial match scaled by their similarity, if it is at least this value between 0 and 1. This is slower")
+                .num_args(0..=1)
+                .default_missing_value("0.8")
+                .value_parser(parse_ratio),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Print more detailed information, like the songs that weren't found, pass twice for debug output")
+                .action(ArgAction::Count),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only print errors, confirmation prompts and the playlist written with --stdout")
+                .conflicts_with("verbose")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("relative")
+                .short('r')
+                .long("relative")
+                .help("Write song paths relative to the output directory, if possible")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("resolve-relative-to-music")
+                .long("resolve-relative-to-music")
+                .help("Write song paths relative to the music dir that contains them, wherever the playlists are written, for players that resolve entries from their library root")
+                .conflicts_with_all(["relative", "strip-prefix", "export"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("strip-prefix")
+                .long("strip-prefix")
+                .value_name("prefix")
+                .help("Remove this prefix from the written song paths, for players that have their own base directory")
+                .num_args(1)
+                .conflicts_with_all(["relative", "export"])
+                .value_hint(ValueHint::DirPath),
+        )
+        .arg(
+            Arg::new("prepend-prefix")
+                .long("prepend-prefix")
+                .value_name("prefix")
+                .help("Prepend this prefix to the written song paths, after --relative, --strip-prefix or --resolve-relative-to-music are applied, to target another device or mount point")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("no-decode-duration")
+                .long("no-decode-duration")
+                .help("Don't read the duration of songs from the audio stream, which is faster but writes unknown durations")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-cache")
+                .long("no-cache")
+                .help("Read the metadata of all songs instead of using the cache in the output directory")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("from-stdin")
+                .long("from-stdin")
+                .help("Localize a m3u playlist read from stdin instead of the playlists in the music or playlist dir")
+                .conflicts_with("playlist-zip")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .help("The name of the playlist read from stdin")
+                .num_args(1)
+                .default_value("stdin")
+                .requires("from-stdin"),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("file")
+                .help("Write a json report of how the songs of each playlist were matched, also with --dry-run")
+                .num_args(1)
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("dump-index")
+                .long("dump-index")
+                .value_name("file")
+                .help("Write the indexed songs by the normalized file names they are matched with as json, to debug matches")
+                .num_args(1)
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("dump-index-only")
+                .long("dump-index-only")
+                .help("Exit after writing the index passed with --dump-index, without localizing the playlists")
+                .requires("dump-index")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stdout")
+                .long("stdout")
+                .help("Write the playlist to stdout instead of the output directory, requires exactly one playlist")
+                .conflicts_with_all(["dry-run", "write-index", "journal", "copy-files", "symlink-files"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("copy-files")
+                .long("copy-files")
+                .help("Copy the matched songs into the output directory and write the playlists with relative paths to the copies")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("symlink-files")
+                .long("symlink-files")
+                .help("Like --copy-files, but create symlinks to the matched songs instead of copying them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("media-dir")
+                .long("media-dir")
+                .value_name("dir")
+                .help("The subdirectory of the output directory that --copy-files or --symlink-files puts the songs into")
+                .num_args(1)
+                .requires("export")
+                .value_hint(ValueHint::DirPath),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .short('n')
+                .long("dry-run")
+                .help("Print the playlists that would be written without writing anything")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("name")
+                .help("Use the defaults for a player or device, which are overridden by the other arguments: android writes extm3u with lf line endings and relative paths, see --prepend-prefix for a base path on the device, foobar writes extm3u with crlf line endings and vlc writes xspf")
+                .num_args(1)
+                .value_parser(PROFILES.map(|p| p.name)),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("file")
+                .help("The toml config file with defaults for music-dir, output-dir, format and extension, instead of the one in the config directory")
+                .num_args(1)
+                .value_hint(ValueHint::FilePath),
+        )
+        .group(
+            ArgGroup::new("export")
+                .args(["copy-files", "symlink-files"])
+                .multiple(false),
+        );
+
+    app = match config.music_dir.is_empty() {
+        true => app.mut_arg("music-dir", |a| {
+            a.required_unless_present("generate-completion")
+        }),
+        false => app.mut_arg("music-dir", |a| a.default_values(config.music_dir)),
+    };
+    app = match config.output_dir {
+        Some(d) => app.mut_arg("output-dir", |a| a.default_value(d)),
+        None => app.mut_arg("output-dir", |a| {
+            a.required_unless_present_any(["generate-completion", "stdout", "in-place"])
+        }),
+    };
+    if let Some(f) = config.format {
+        app = app.mut_arg("format", |a| a.default_value(f));
+    }
+    if let Some(e) = config.extension {
+        app = app.mut_arg("output-file-extension", |a| a.default_value(e));
+    }
+    if let Some(profile) = Profile::from_args(&args) {
+        app = app
+            .mut_arg("format", |a| a.default_value(profile.format))
+            .mut_arg("line-ending", |a| a.default_value(profile.line_ending));
+        if profile.relative {
+            app = app.mut_arg("relative", |a| a.default_value("true"));
+        }
+    }
+
+    let matches = app.clone().get_matches_from(args);
+
+    let level = log_level(matches.get_flag("quiet"), matches.get_count("verbose"));
+    let progress = MultiProgress::new();
+    if matches.get_flag("quiet") || !std::io::stdout().is_terminal() {
+        progress.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    init_logger(level, &progress);
+
+    let generate_completion = matches.get_one("generate-completion");
+
+    if let Some(shell) = generate_completion {
+        let mut stdout = std::io::stdout();
+        match shell {
+            Shell::Bash => generate(Bash, &mut app, BIN_NAME, &mut stdout),
+            Shell::Elvish => generate(Elvish, &mut app, BIN_NAME, &mut stdout),
+            Shell::Fish => generate(Fish, &mut app, BIN_NAME, &mut stdout),
+            Shell::Zsh => generate(Zsh, &mut app, BIN_NAME, &mut stdout),
+            Shell::Pwrsh => generate(PowerShell, &mut app, BIN_NAME, &mut stdout),
+        }
+        return Ok(());
+    }
+
+    let threads = match matches.get_flag("deterministic") {
+        true => Some(1),
+        false => matches.get_one::<usize>("threads").copied(),
+    };
+    if let Some(threads) = threads {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads);
+        if let Err(e) = pool.build_global() {
+            error!("Couldn't create thread pool because:\n{:?}", e);
+        }
+    }
+
+    if !matches.get_flag("watch") {
+        return localize_all(&matches, &progress);
+    }
+    let dirs: Vec<PathBuf> = match matches.get_one::<String>("playlist-dir") {
+        Some(d) => vec![d.into()],
+        None => matches
+            .get_many::<String>("music-dir")
+            .unwrap()
+            .map(PathBuf::from)
+            .collect(),
+    };
+    let mut extensions: Vec<String> = PLAYLIST_EXTENSIONS.map(String::from).to_vec();
+    if let Some(added) = matches.get_many::<String>("playlist-ext") {
+        extensions.extend(added.map(|e| e.trim_start_matches('.').to_string()));
+    }
+    let output_dir = matches
+        .get_one::<String>("output-dir")
+        .map(|o| watch::output_dir_path(o.as_ref()));
+    watch::watch(&dirs, &extensions, output_dir.as_deref(), || {
+        localize_all(&matches, &progress)
+    })
+}
+
+/// Localizes and writes the playlists once.
+fn localize_all(matches: &ArgMatches, progress: &MultiProgress) -> Result<(), AppError> {
+    let music_dirs: Vec<PathBuf> = matches
+        .get_many::<String>("music-dir")
+        .unwrap()
+        .map(PathBuf::from)
+        .collect();
+    // relative paths written to stdout are relative to the current directory
+    let output_dir = matches
+        .get_one::<String>("output-dir")
+        .map(String::as_str)
+        .unwrap_or(".");
+    let formats = matches.get_one::<Vec<PlaylistFormat>>("format").unwrap();
+    let extensions: Vec<&str> = matches
+        .get_many::<String>("output-file-extension")
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    // every output format and the extension of the files it is written to
+    let outputs = output_extensions(formats, &extensions)?;
+    for (format, extension) in outputs.iter() {
+        if format.conflicts_with(extension) {
+            warn!(
+                "the output file extension '{extension}' is conventionally used by another format"
+            );
+        }
+    }
+    let format = outputs[0].0;
+    let export_files = matches.get_flag("copy-files") || matches.get_flag("symlink-files");
+    let mut format_options = FormatOptions {
+        number_entries: matches.get_flag("number-entries"),
+        canonical_paths: matches.get_flag("canonical-output-paths"),
+        csv_columns: matches
+            .get_many::<CsvColumn>("csv-columns")
+            .unwrap()
+            .copied()
+            .collect(),
+        csv_header: matches.get_flag("csv-header"),
+        split_embedded_cue: matches.get_flag("split-embedded-cue"),
+        genre_from_depth: matches.get_one("genre-from-depth").copied(),
+        art_names: matches
+            .get_many::<String>("art-names")
+            .map(|n| n.cloned().collect())
+            .unwrap_or_default(),
+        json_pretty: matches.get_flag("json-pretty")
+            || (!matches.get_flag("json-compact") && std::io::stdout().is_terminal()),
+        relative: matches.get_flag("relative") || export_files,
+        base_comment: matches.get_flag("emit-base-comment"),
+        preserve_comments: matches.get_flag("preserve-comments"),
+        keep_missing: matches.get_flag("keep-missing"),
+        flatten: matches.get_flag("flatten-output"),
+        lossy_paths: matches.get_flag("lossy-paths"),
+        prefer_file_tags: matches.get_flag("prefer-file-tags"),
+        line_ending: matches.get_one("line-ending").copied().unwrap(),
+        extinf_template: matches.get_one::<String>("extinf-template").cloned(),
+        strip_prefix: matches.get_one::<String>("strip-prefix").map(PathBuf::from),
+        prepend_prefix: matches
+            .get_one::<String>("prepend-prefix")
+            .map(PathBuf::from),
+        no_decode_duration: matches.get_flag("no-decode-duration"),
+        artist_separator: matches.get_one::<String>("artist-separator").cloned(),
+        overwrite: match (matches.get_flag("no-overwrite"), matches.get_flag("backup")) {
+            (true, _) => Overwrite::Never,
+            (_, true) => Overwrite::Backup,
+            _ => Overwrite::Always,
+        },
+        ..Default::default()
+    };
+    let stem_options = StemOptions {
+        fold_accents: matches.get_flag("fold-accents"),
+        ignore_case: matches.get_flag("ignore-case"),
+        strip_track_prefix: matches.get_flag("strip-track-prefix"),
+    };
+    let mut extensions = Extensions::default();
+    if let Some(added) = matches.get_many::<String>("music-ext") {
+        for e in added.map(|e| e.trim_start_matches('.').to_lowercase()) {
+            if !e.is_empty() && !extensions.music.contains(&e) {
+                extensions.music.push(e);
+            }
+        }
+    }
+    if let Some(added) = matches.get_many::<String>("playlist-ext") {
+        for e in added.map(|e| e.trim_start_matches('.').to_lowercase()) {
+            if !e.is_empty() && !extensions.playlist.contains(&e) {
+                extensions.playlist.push(e);
+            }
+        }
+    }
+    if let Some(skipped) = matches.get_many::<String>("skip-music-ext") {
+        let skipped: Vec<String> = skipped
+            .map(|e| e.trim_start_matches('.').to_lowercase())
+            .collect();
+        extensions.music.retain(|e| !skipped.contains(e));
+    }
+    let match_options = MatchOptions {
+        stem: stem_options,
+        fuzzy: matches.get_one("fuzzy").copied(),
+        fuzzy_dirs: matches.get_one("fuzzy-dirs").copied(),
+        verify_exists: matches.get_flag("verify-exists"),
+        verify_matches: matches.get_flag("verify-matches"),
+        min_confidence: matches.get_one("min-confidence").copied().unwrap(),
+        min_matching_components: matches.get_one("min-matching-components").copied().unwrap(),
+        prefer_artist_folder: matches.get_flag("prefer-artist-folder"),
+        keep_urls: matches.get_flag("keep-urls"),
+        prefer_extensions: matches
+            .get_many::<String>("prefer-ext")
+            .map(|e| {
+                e.map(|e| e.trim_start_matches('.').to_lowercase())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        extension_priority: matches.get_flag("extension-priority"),
+        exclude_stems: matches
+            .get_many::<String>("exclude-stem")
+            .map(|s| s.map(OsString::from).collect())
+            .unwrap_or_default(),
+        exclude_paths: matches
+            .get_many::<Regex>("exclude-path-regex")
+            .map(|r| r.cloned().collect())
+            .unwrap_or_default(),
+    };
+    let index_options = IndexOptions {
+        stem: stem_options,
+        extensions,
+        collapse_symlinks: matches.get_one("collapse-symlinks").copied(),
+        follow_symlinks: matches.get_flag("follow-symlinks"),
+        playlist_dir: matches.get_one::<String>("playlist-dir").map(PathBuf::from),
+        skip_hidden: matches.get_flag("skip-hidden"),
+        max_depth: matches.get_one("index-depth").copied(),
+        no_canonicalize: matches.get_flag("no-canonicalize"),
+        sniff: matches.get_flag("sniff"),
+        include: glob_set(matches, "include")?,
+        exclude: glob_set(matches, "exclude")?,
+        ignore_dirs: matches
+            .get_many::<String>("ignore-dir")
+            .map(|d| d.map(OsString::from).collect())
+            .unwrap_or_default(),
+    };
+
+    info!("indexing...");
+    let indexing_start = Instant::now();
+    let mut music_index = index_music_dirs(&music_dirs, &index_options, progress)?;
+    if matches.get_flag("resolve-relative-to-music") {
+        format_options.music_roots = music_index.roots.clone();
+    }
+
+    // the written playlists and exported songs would be indexed again by the next run
+    let writes_output_dir = !matches.get_flag("in-place") && !matches.get_flag("stdout");
+    let indexed_dirs = music_dirs.iter().chain(&index_options.playlist_dir);
+    let output_root = canonicalize(output_dir.as_ref())
+        .or_else(|_| std::path::absolute(output_dir).map(|p| normalize_path(&p)))
+        .ok()
+        .filter(|_| writes_output_dir);
+    let overlap = output_root.as_ref().and_then(|o| {
+        indexed_dirs
+            .filter_map(|d| index_options.root_path(d).ok())
+            .find(|d| o.starts_with(d))
+            .map(|d| (o, d))
+    });
+    if let Some((output_root, indexed_dir)) = overlap {
+        if matches.get_flag("strict") {
+            return Err(AppError::OutputInIndexedDir {
+                output_dir: output_root.clone(),
+                indexed_dir,
+            });
+        }
+        warn!(
+            "the output dir {} is inside the indexed dir {}, the files in it are skipped",
+            output_root.display(),
+            indexed_dir.display()
+        );
+        music_index.songs.retain(|_, songs| {
+            songs.retain(|s| !s.starts_with(output_root));
+            !songs.is_empty()
+        });
+        music_index
+            .playlists
+            .retain(|p| !p.starts_with(output_root));
+    }
+    if matches.get_flag("dedup-index") {
+        let collapsed = music_index.dedup();
+        info!("{collapsed} groups of identical songs collapsed");
+    }
+    if let Some(since) = matches.get_one::<Duration>("since") {
+        let cutoff = SystemTime::now().checked_sub(*since).unwrap_or(UNIX_EPOCH);
+        music_index.playlists.retain(|p| {
+            let modified = std::fs::metadata(p).and_then(|m| m.modified());
+            let recent = modified.is_ok_and(|m| m >= cutoff);
+            if !recent {
+                debug!("skipping {}, it wasn't modified recently", p.display());
+            }
+            recent
+        });
+    }
+    if matches.contains_id("playlist-glob") {
+        let globs = glob_set(matches, "playlist-glob")?;
+        music_index.playlists.retain(|p| {
+            let matched = p.file_name().is_some_and(|n| globs.is_match(n));
+            if !matched {
+                debug!("skipping {}, it doesn't match --playlist-glob", p.display());
+            }
+            matched
+        });
+    }
+    if let Some(limit) = matches.get_one::<usize>("limit").copied() {
+        // the playlists are sorted, so the same ones are picked every time
+        music_index.playlists.truncate(limit);
+    }
+
+    if let Some(path) = matches.get_one::<String>("dump-index") {
+        report::write_index_dump(path.as_ref(), &music_index).map_err(|e| AppError::Report {
+            path: path.into(),
+            source: e,
+        })?;
+        if matches.get_flag("dump-index-only") {
+            return Ok(());
+        }
+    }
+
+    if matches.get_flag("warn-duplicates") {
+        let hash = matches.get_flag("hash-duplicates");
+        for (stem, songs) in duplicate_stems(&music_index) {
+            warn!(
+                "{} songs named \"{}\" in different directories",
+                songs.len(),
+                stem.to_string_lossy()
+            );
+            if !hash {
+                for s in songs {
+                    info!("  {}", s.display());
+                }
+                continue;
+            }
+            for group in content_groups(songs) {
+                let (first, same) = group.split_first().unwrap();
+                info!("  {}", first.display());
+                for s in same {
+                    info!("  {} (same content)", s.display());
+                }
+            }
+        }
+    }
+
+    let fallback_index = match matches.get_one::<String>("fallback-dir") {
+        Some(d) => {
+            info!("indexing fallback dir...");
+            let fallback_options = IndexOptions {
+                playlist_dir: None,
+                ..index_options.clone()
+            };
+            Some(index_music_dirs(&[d.into()], &fallback_options, progress)?)
+        }
+        None => None,
+    };
+
+    let tag_index = match matches.get_flag("match-tags") {
+        true => {
+            info!("reading tags...");
+            Some(tag_index(&music_index, &stem_options))
+        }
+        false => None,
+    };
+
+    let playlist_roots: Vec<PathBuf> = match matches.get_flag("preserve-tree") {
+        true => match &index_options.playlist_dir {
+            Some(d) => vec![d.clone()],
+            None => music_dirs.clone(),
+        }
+        .iter()
+        .filter_map(|d| index_options.root_path(d).ok())
+        .collect(),
+        false => Vec::new(),
+    };
+
+    let max_depth = matches.get_one("max-depth").copied().unwrap();
+    // the source dirs are absolute, so they replace the output dir when joined to it
+    let in_place = matches.get_flag("in-place");
+    let itunes_xml = matches.get_one::<String>("itunes-xml");
+    let copy_hls = matches.get_flag("copy-hls");
+    // the source and output path, relative to the output dir, of HLS playlists copied unchanged
+    let mut hls_copies: Vec<(PathBuf, PathBuf)> = Vec::new();
+    // the name, output dir, base dir of relative entries, format override and entries
+    type Source = (
+        String,
+        PathBuf,
+        Option<PathBuf>,
+        Option<PlaylistFormat>,
+        Vec<Entry>,
+    );
+    let mut sources: Vec<Source> = if matches.get_flag("from-stdin") {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(AppError::Stdin)?;
+        let contents = decode_playlist(&bytes, None);
+        let name = matches.get_one::<String>("name").unwrap().clone();
+        if is_hls(&contents) {
+            warn!("{name}: skipping, it's an HLS playlist whose segments can't be localized");
+        }
+        let entries = parse_playlist_as(&contents, PlaylistInputFormat::M3u);
+        let entries = skip_nested(entries, &name, &index_options.extensions);
+        vec![(name, PathBuf::new(), None, None, entries)]
+    } else if let Some(xml) = itunes_xml {
+        let playlists = itunes_playlists(xml.as_ref()).map_err(|e| AppError::ItunesXml {
+            path: xml.into(),
+            source: e,
+        })?;
+        playlists
+            .into_iter()
+            .map(|(name, entries)| (name, PathBuf::new(), None, None, entries))
+            .collect()
+    } else if let Some(xml) = matches.get_one::<String>("rekordbox-xml") {
+        let playlists = rekordbox_playlists(xml.as_ref()).map_err(|e| AppError::RekordboxXml {
+            path: xml.into(),
+            source: e,
+        })?;
+        playlists
+            .into_iter()
+            .map(|(name, entries)| (name, PathBuf::new(), None, None, entries))
+            .collect()
+    } else if matches.get_flag("folders-as-playlists") {
+        song_folders(&music_index)
+            .into_iter()
+            .filter_map(|(folder, songs)| {
+                let name = folder.file_name().and_then(|s| s.to_str())?;
+                let dir = playlist_roots
+                    .iter()
+                    .find_map(|r| folder.parent()?.strip_prefix(r).ok())
+                    .map(PathBuf::from)
+                    .unwrap_or_default();
+                let entries = songs
+                    .into_iter()
+                    .map(|s| Entry {
+                        path: s.to_path_buf(),
+                        extinf: None,
+                        comments: Vec::new(),
+                    })
+                    .collect();
+                Some((name.to_string(), dir, None, None, entries))
+            })
+            .collect()
+    } else {
+        music_index
+            .playlists
+            .iter()
+            .filter_map(|p| {
+                let name = p.file_stem().and_then(|s| s.to_str())?;
+                let dir = playlist_roots
+                    .iter()
+                    .find_map(|r| p.parent()?.strip_prefix(r).ok())
+                    .map(PathBuf::from)
+                    .unwrap_or_default();
+                let dir = match in_place {
+                    true => p.parent().map(Path::to_path_buf).unwrap_or_default(),
+                    false => dir,
+                };
+                let is_output = |(_, e): &&(PlaylistFormat, &str)| {
+                    p.to_str().is_some_and(|p| p.ends_with(&format!(".{e}")))
+                };
+                if let Some((_, extension)) = outputs.iter().find(is_output).filter(|_| in_place) {
+                    if p.extension().is_some_and(|e| e == *extension) {
+                        warn!(
+                            "skipping {}, it would be overwritten by its output, pass another --output-file-extension",
+                            p.display()
+                        );
+                    } else {
+                        debug!("skipping {}, it's the output of a previous run", p.display());
+                    }
+                    return None;
+                }
+                if read_playlist(p).is_ok_and(|c| is_hls(&c)) {
+                    match copy_hls {
+                        true => hls_copies.push((p.clone(), dir.join(p.file_name()?))),
+                        false => warn!(
+                            "skipping {}, it's an HLS playlist whose segments can't be localized, pass --copy-hls to copy it unchanged",
+                            p.display()
+                        ),
+                    }
+                    return None;
+                }
+                let entries = m3u_playlist_paths(p);
+                let entries = expand_nested(entries, p, &index_options.extensions, max_depth);
+                let base = p.parent().map(Path::to_path_buf);
+                Some((name.to_string(), dir, base, format_override(p), entries))
+            })
+            .collect()
+    };
+
+    if let Some(zip) = matches.get_one::<String>("playlist-zip") {
+        let playlists = zip_playlists(zip.as_ref(), &index_options.extensions).map_err(|e| {
+            AppError::PlaylistZip {
+                path: zip.into(),
+                source: e,
+            }
+        })?;
+        sources.extend(
+            playlists
+                .into_iter()
+                .map(|(name, entries)| (name, PathBuf::new(), None, None, entries)),
+        );
+    }
+
+    let verbose = matches.get_count("verbose") > 0;
+    let dedup = matches.get_flag("dedup");
+    let base_dir = matches.get_one::<PathBuf>("base-dir").map(PathBuf::as_path);
+
+    let indexing_time = indexing_start.elapsed();
+    info!("localizing songs...");
+    let localizing_start = Instant::now();
+    let bar = progress.add(count_bar(sources.len(), "playlists"));
+    let mut excluded_count = 0;
+    let mut unverified_count = 0;
+    let (mut playlists, match_reports): (Vec<Playlist>, Vec<_>) = sources
+        .into_iter()
+        .map(|(name, dir, base, format, entries)| {
+            bar.inc(1);
+            let (playlist, stats) = m3u_playlist(
+                &DefaultMatcher,
+                &music_index,
+                fallback_index.as_ref(),
+                tag_index.as_ref(),
+                &match_options,
+                &entries,
+                base_dir.or(base.as_deref()),
+                name,
+            );
+            let mut playlist = playlist.with_dir(dir).with_format(format);
+            if stats.fallback > 0 {
+                info!(
+                    "playlist \"{}\": {} songs matched in the fallback dir (lower confidence)",
+                    playlist.name(),
+                    stats.fallback,
+                );
+            }
+            if stats.tags > 0 {
+                info!(
+                    "playlist \"{}\": {} songs matched by their tags",
+                    playlist.name(),
+                    stats.tags,
+                );
+            }
+            if stats.excluded > 0 {
+                info!(
+                    "playlist \"{}\": {} songs excluded",
+                    playlist.name(),
+                    stats.excluded,
+                );
+                excluded_count += stats.excluded;
+            }
+            if stats.unverified > 0 {
+                warn!(
+                    "playlist \"{}\": {} matched songs left out because they don't look like audio files",
+                    playlist.name(),
+                    stats.unverified,
+                );
+                unverified_count += stats.unverified;
+            }
+            if stats.low_confidence > 0 {
+                info!(
+                    "playlist \"{}\": {} songs rejected because of a low match confidence or too few matching directories",
+                    playlist.name(),
+                    stats.low_confidence,
+                );
+            }
+            if !playlist.unmatched().is_empty() {
+                let total = playlist.songs().len() + playlist.unmatched().len();
+                warn!(
+                    "playlist \"{}\": {}/{} songs not found",
+                    playlist.name(),
+                    playlist.unmatched().len(),
+                    total,
+                );
+                for p in playlist.unmatched() {
+                    debug!("  {}", p.display());
+                }
+            }
+            if format_options.flatten {
+                warn_flattened_duplicates(&playlist);
+            }
+            if dedup {
+                let removed = playlist.dedup();
+                if removed > 0 {
+                    info!(
+                        "playlist \"{}\": {} duplicate songs removed",
+                        playlist.name(),
+                        removed,
+                    );
+                }
+            }
+
+            (playlist, stats.entries)
+        })
+        .unzip();
+    bar.finish_and_clear();
+    let localizing_time = localizing_start.elapsed();
+
+    let playlist_count = playlists.len();
+    let song_count: usize = match_reports.iter().map(Vec::len).sum();
+    let matched_count = match_reports
+        .iter()
+        .flatten()
+        .filter(|e| e.resolved.is_some())
+        .count();
+
+    if let Some(path) = matches.get_one::<String>("report") {
+        let reports: Vec<_> = playlists
+            .iter()
+            .map(|p| p.name().to_string())
+            .zip(match_reports)
+            .collect();
+        report::write_report(path.as_ref(), &reports).map_err(|e| AppError::Report {
+            path: path.into(),
+            source: e,
+        })?;
+    }
+
+    if let Some(name) = matches.get_one::<String>("merge") {
+        let mut merged = Playlist::merge(name.clone(), playlists);
+        if dedup {
+            let removed = merged.dedup();
+            if removed > 0 {
+                info!(
+                    "playlist \"{}\": {} duplicate songs removed",
+                    merged.name(),
+                    removed,
+                );
+            }
+        }
+        playlists = vec![merged];
+    }
+
+    match matches.get_one("on-empty").copied().unwrap() {
+        OnEmpty::Write => (),
+        OnEmpty::Skip => playlists.retain(|p| !p.songs().is_empty()),
+        OnEmpty::Error => {
+            if let Some(p) = playlists.iter().find(|p| p.songs().is_empty()) {
+                return Err(AppError::EmptyPlaylist {
+                    name: p.name().to_string(),
+                });
+            }
+        }
+    }
+
+    let export = match export_files {
+        true => {
+            let mut dir = canonicalize(output_dir.as_ref())
+                .or_else(|_| std::path::absolute(output_dir))
+                .map_err(|e| AppError::InvalidOutputDir {
+                    path: output_dir.into(),
+                    source: e,
+                })?;
+            if let Some(media_dir) = matches.get_one::<String>("media-dir") {
+                dir.push(media_dir);
+            }
+            let mut export = Export::new(dir);
+            for s in playlists.iter().flat_map(|p| p.songs()) {
+                export.add(s);
+            }
+            Some(export)
+        }
+        false => None,
+    };
+    if let Some(export) = &export {
+        playlists = playlists
+            .into_iter()
+            .map(|p| p.map_songs(|s| export.destination(s)))
+            .collect();
+    }
+
+    if let Some(size) = matches.get_one::<usize>("chunk-size").copied() {
+        playlists = playlists
+            .into_iter()
+            .flat_map(|p| p.into_chunks(size))
+            .collect();
+    }
+
+    let name_template = matches.get_one::<String>("output-name-template").unwrap();
+    if name_template != "<name>" {
+        let date = utc_date();
+        playlists = playlists
+            .into_iter()
+            .map(|p| {
+                let file_name = output_name(name_template, p.name(), &date);
+                p.with_file_name(file_name)
+            })
+            .collect();
+    }
+
+    if let Some(dir) = matches.get_one::<String>("diff-against") {
+        info!("comparing to previous run...");
+        diff::print_diff(dir.as_ref(), &playlists, &outputs, &format_options);
+    }
+
+    if matches.get_flag("stdout") {
+        if outputs.len() > 1 {
+            warn!("only the first format is written to stdout");
+        }
+        let [playlist] = playlists.as_slice() else {
+            return Err(AppError::Stdout {
+                count: playlists.len(),
+            });
+        };
+        let content = playlist
+            .format_in(output_dir.as_ref(), format, &format_options)
+            .map_err(|e| AppError::InvalidOutputDir {
+                path: output_dir.into(),
+                source: e,
+            })?;
+        print!("{content}");
+        return Ok(());
+    }
+
+    let yes = matches.get_flag("yes");
+    let confirm_threshold = matches.get_one("confirm-threshold").copied().unwrap();
+    let strict = matches.get_flag("strict");
+    let dry_run = matches.get_flag("dry-run");
+
+    let overwritten = playlists
+        .iter()
+        .flat_map(|p| {
+            playlist_outputs(p, &outputs)
+                .into_iter()
+                .map(|(_, e)| p.file_path(output_dir.as_ref(), e))
+        })
+        .filter(|p| p.exists())
+        .count();
+    let overwrites = format_options.overwrite != Overwrite::Never;
+    if overwrites && overwritten > confirm_threshold && !yes && !dry_run {
+        // the answer is read from stdin, which could be a pipe even if stdout is a terminal
+        if std::io::stdout().is_terminal() && std::io::stdin().is_terminal() {
+            if !confirm(&format!(
+                "{overwritten} existing files in {output_dir} would be overwritten. Continue?"
+            )) {
+                return Err(AppError::Aborted);
+            }
+        } else if strict {
+            return Err(AppError::Overwrite {
+                count: overwritten,
+                dir: output_dir.into(),
+            });
+        }
+    }
+
+    let journal_path = matches.get_one::<String>("journal").filter(|_| !dry_run);
+    let journal = match journal_path {
+        Some(j) => Some(
+            Journal::open(j.as_ref(), matches.get_flag("resume")).map_err(|e| {
+                AppError::Journal {
+                    path: j.into(),
+                    source: e,
+                }
+            })?,
+        ),
+        None => None,
+    };
+
+    if !dry_run {
+        if let Err(e) = std::fs::create_dir_all(output_dir) {
+            return Err(AppError::InvalidOutputDir {
+                path: output_dir.into(),
+                source: e,
+            });
+        }
+    }
+
+    if let Some(export) = export.as_ref().filter(|_| !dry_run) {
+        let symlink = matches.get_flag("symlink-files");
+        match symlink {
+            true => info!("linking songs..."),
+            false => info!("copying songs..."),
+        }
+        let bar = progress.add(count_bar(export.len(), "songs"));
+        let (mut copied, mut failed) = (0, 0);
+        for (source, dest) in export.files() {
+            bar.inc(1);
+            let result = match symlink {
+                true => link_file(source, dest),
+                false => copy_file(source, dest),
+            };
+            match result {
+                Ok(Exported::Written) => copied += 1,
+                Ok(Exported::Unchanged) => (),
+                Err(e) => {
+                    error!("Couldn't export {} because:\n{:?}", source.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+        bar.finish_and_clear();
+        info!(
+            "{copied} songs exported, {} already up to date",
+            export.len() - copied - failed
+        );
+        if failed > 0 {
+            return Err(AppError::Copy { failed });
+        }
+    }
+
+    // plain m3u playlists don't contain any metadata
+    let sort: SortOrder = matches.get_one("sort").copied().unwrap();
+    let metadata_formats = outputs
+        .iter()
+        .filter(|(f, _)| !matches!(f, PlaylistFormat::M3u | PlaylistFormat::Wpl))
+        .count();
+    let needs_metadata = metadata_formats > 0 || sort.needs_metadata();
+    let cache_path = Path::new(output_dir).join(CACHE_FILE_NAME);
+    // there's no single output directory to keep the cache in
+    let save_cache = !dry_run && !in_place && !matches.get_flag("no-cache") && needs_metadata;
+    if save_cache {
+        format_options.metadata_cache = Some(Arc::new(MetadataCache::load(&cache_path)));
+    } else if sort.needs_metadata() || metadata_formats > 1 {
+        // only reuse the tags read for sorting or another format when formatting
+        format_options.metadata_cache = Some(Arc::default());
+    }
+
+    let writing_start = Instant::now();
+    if dry_run {
+        info!("dry run, nothing is written");
+    } else {
+        info!("writing playlists...");
+    }
+    // playlists with the same name in different dirs of a flat layout would be written to the
+    // same file concurrently, so each file is only written by the first of them
+    let mut writers: HashMap<PathBuf, usize> = HashMap::new();
+    for (i, p) in playlists.iter().enumerate() {
+        for (_, extension) in playlist_outputs(p, &outputs) {
+            let file_path = p.file_path(output_dir.as_ref(), extension);
+            let first = *writers.entry(file_path.clone()).or_insert(i);
+            if first != i {
+                warn!(
+                    "playlists \"{}\" and \"{}\" would both be written to {}, only the first one is written",
+                    playlists[first].name(),
+                    p.name(),
+                    file_path.display()
+                );
+            }
+        }
+    }
+
+    let bar = progress.add(count_bar(playlists.len(), "playlists"));
+    let journal = journal.map(Mutex::new);
+    let written = Mutex::new(Vec::new());
+    // writes the playlist in every pending format, and returns the number of failed writes
+    let write = |(i, p): (usize, &mut Playlist)| {
+        bar.inc(1);
+        let is_completed = |path: &PathBuf| {
+            let journal = journal.as_ref().map(|j| j.lock().unwrap());
+            journal.is_some_and(|j| j.is_completed(path))
+        };
+        let pending: Vec<_> = playlist_outputs(p, &outputs)
+            .into_iter()
+            .map(|(f, e)| (f, e, p.file_path(output_dir.as_ref(), e)))
+            .filter(|(_, _, path)| writers.get(path) == Some(&i) && !is_completed(path))
+            .collect();
+        if pending.is_empty() {
+            return 0;
+        }
+
+        p.sort(sort, &format_options);
+
+        let mut failed = 0;
+        for (format, extension, file_path) in pending {
+            if dry_run {
+                bar.suspend(|| p.preview(output_dir.as_ref(), extension, verbose));
+                continue;
+            }
+
+            if let Err(e) = p.write_to(output_dir.as_ref(), format, extension, &format_options) {
+                error!("Couldn't write {} because:\n{:?}", file_path.display(), e);
+                failed += 1;
+                continue;
+            }
+            written.lock().unwrap().push((file_path.clone(), format));
+
+            if let Some(j) = &journal {
+                if let Err(e) = j.lock().unwrap().record(&file_path) {
+                    error!("Couldn't update journal because:\n{:?}", e);
+                }
+            }
+        }
+        failed
+    };
+    // playlists are independent, so they are written in parallel, but previews are printed in
+    // their order
+    let mut failed: usize = match dry_run {
+        true => playlists.iter_mut().enumerate().map(write).sum(),
+        false => playlists.par_iter_mut().enumerate().map(write).sum(),
+    };
+
+    bar.finish_and_clear();
+
+    for (source, dest) in hls_copies.iter().filter(|_| !dry_run) {
+        let dest = Path::new(output_dir).join(dest);
+        let copied = dest.parent().map_or(Ok(()), std::fs::create_dir_all);
+        if let Err(e) = copied.and_then(|_| std::fs::copy(source, &dest)) {
+            error!("Couldn't copy {} because:\n{:?}", source.display(), e);
+            failed += 1;
+        }
+    }
+
+    if let Some(cache) = format_options
+        .metadata_cache
+        .as_ref()
+        .filter(|_| save_cache)
+    {
+        if let Err(e) = cache.save(&cache_path) {
+            error!("Couldn't write metadata cache because:\n{:?}", e);
+        }
+    }
+
+    if let Some(name) = matches
+        .get_one::<String>("write-index")
+        .filter(|_| !dry_run)
+    {
+        info!("writing index...");
+        for (format, extension) in outputs.iter() {
+            let written = write_index(
+                output_dir.as_ref(),
+                name,
+                &playlists,
+                *format,
+                extension,
+                format_options.line_ending,
+            );
+            if let Err(e) = written {
+                error!("Couldn't write index {name} because:\n{:?}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    let writing_time = writing_start.elapsed();
+
+    let missing = match matches.get_flag("self-test") {
+        true => {
+            info!("checking the written playlists...");
+            let mut written = written.into_inner().unwrap();
+            written.sort_by(|(a, _), (b, _)| a.cmp(b));
+            self_test(&written, &format_options)
+        }
+        false => 0,
+    };
+
+    let excluded = match excluded_count {
+        0 => String::new(),
+        n => format!(", {n} excluded"),
+    };
+    let unverified = match unverified_count {
+        0 => String::new(),
+        n => format!(", {n} failed verification"),
+    };
+    info!(
+        "{playlist_count} playlists, {matched_count} songs matched, {} not found{excluded}{unverified}",
+        song_count - matched_count
+    );
+    info!(
+        "indexing took {:.2}s, localizing {:.2}s, writing {:.2}s",
+        indexing_time.as_secs_f64(),
+        localizing_time.as_secs_f64(),
+        writing_time.as_secs_f64()
+    );
+
+    if failed > 0 {
+        return Err(AppError::Write { failed });
+    }
+    if missing > 0 {
+        return Err(AppError::SelfTest { missing });
+    }
+
+    info!("done");
+
+    if strict && playlists.iter().any(|p| !p.unmatched().is_empty()) {
+        return Err(AppError::Unmatched);
+    }
+
+    Ok(())
+}
+
+/// The level of the messages that are printed. The status messages are logged at the info level,
+/// so `quiet` only leaves errors.
+fn log_level(quiet: bool, verbosity: u8) -> LevelFilter {
+    match (quiet, verbosity) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+fn init_logger(level: LevelFilter, progress: &MultiProgress) {
+    // only show the debug output of dependencies if it's requested through RUST_LOG
+    let logger = env_logger::Builder::new()
+        .filter_level(level.min(LevelFilter::Warn))
+        .filter_module("playlist_localizer", level)
+        .format(|buf, record| match record.level() {
+            Level::Error => writeln!(buf, "error: {}", record.args()),
+            Level::Warn => writeln!(buf, "warning: {}", record.args()),
+            _ => writeln!(buf, "{}", record.args()),
+        })
+        .parse_default_env()
+        .build();
+    let max_level = logger.filter();
+
+    // log through the progress bars, so they are redrawn below the messages
+    if LogWrapper::new(progress.clone(), logger).try_init().is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+fn glob_set(matches: &ArgMatches, id: &str) -> Result<GlobSet, AppError> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in matches.get_many::<Glob>(id).into_iter().flatten() {
+        builder.add(glob.clone());
+    }
+    builder.build().map_err(AppError::Glob)
+}
+
+fn count_bar(len: usize, unit: &str) -> ProgressBar {
+    let style = ProgressStyle::with_template(&format!("{{bar:40}} {{pos}}/{{len}} {unit}"))
+        .unwrap_or_else(|_| ProgressStyle::default_bar());
+    ProgressBar::new(len as u64).with_style(style)
+}
+
+fn index_music_dirs(
+    music_dirs: &[PathBuf],
+    options: &IndexOptions,
+    progress: &MultiProgress,
+) -> Result<MusicIndex, AppError> {
+    let style = ProgressStyle::with_template("{spinner} {pos} files")
+        .unwrap_or_else(|_| ProgressStyle::default_spinner());
+    let spinner = progress.add(ProgressBar::new_spinner().with_style(style));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let result = index_with_progress(music_dirs, options, &spinner);
+    spinner.finish_and_clear();
+
+    result.map_err(AppError::InvalidMusicDir)
+}
+
+/// Warns about different songs of the playlist `p` with the same file name, which can't be told
+/// apart once the paths are flattened.
+fn warn_flattened_duplicates(p: &Playlist) {
+    let mut songs_by_name: BTreeMap<&OsStr, BTreeSet<&Path>> = BTreeMap::new();
+    for song in p.entries() {
+        if let Some(name) = song.file_name() {
+            songs_by_name.entry(name).or_default().insert(song);
+        }
+    }
+    for (name, songs) in songs_by_name.iter().filter(|(_, s)| s.len() > 1) {
+        warn!(
+            "playlist \"{}\": {} different songs are named \"{}\" and can't be told apart with --flatten-output",
+            p.name(),
+            songs.len(),
+            name.to_string_lossy()
+        );
+    }
+}
+
+/// The format read from the sidecar file next to the playlist at `path`, like `Foo.fmt` for
+/// `Foo.m3u`, which contains the name of a format.
+fn format_override(path: &Path) -> Option<PlaylistFormat> {
+    let sidecar = path.with_extension("fmt");
+    let contents = std::fs::read_to_string(&sidecar).ok()?;
+    match contents.trim().parse() {
+        Ok(f) => Some(f),
+        Err(e) => {
+            warn!(
+                "{}: {e} '{}', using the global format",
+                sidecar.display(),
+                contents.trim()
+            );
+            None
+        }
+    }
+}
+
+/// Reads the `written` playlists back and warns about every song path in them that doesn't exist,
+/// returns the number of those paths. URLs aren't checked.
+fn self_test(written: &[(PathBuf, PlaylistFormat)], options: &FormatOptions) -> usize {
+    let mut missing = 0;
+    for (file_path, format) in written {
+        let Some(input_format) = format.input_format() else {
+            debug!(
+                "not checking {}, it can't be read back",
+                file_path.display()
+            );
+            continue;
+        };
+        let dir = file_path.parent().unwrap_or(Path::new(""));
+        for entry in parse_entries(file_path, input_format) {
+            let path = entry.path.to_string_lossy();
+            if path.contains("://") {
+                continue;
+            }
+            // the tracks of embedded cue sheets refer to the whole file
+            let path = match path.rsplit_once("#track=") {
+                Some((p, n)) if n.parse::<u8>().is_ok() => Path::new(p),
+                _ => entry.path.as_path(),
+            };
+            let candidates = options.local_candidates(path, dir);
+            if !candidates.iter().any(|c| c.exists()) {
+                warn!("{}: {} doesn't exist", file_path.display(), path.display());
+                missing += 1;
+            }
+        }
+    }
+    if missing == 0 {
+        info!("all song paths in the written playlists exist");
+    }
+    missing
+}
+
+/// Pairs every one of the `formats` with the extension at the same position of `extensions`.
+/// Without extensions, or with fewer than formats, every format gets its conventional extension,
+/// more extensions than formats are rejected.
+fn output_extensions<'a>(
+    formats: &[PlaylistFormat],
+    extensions: &[&'a str],
+) -> Result<Vec<(PlaylistFormat, &'a str)>, AppError> {
+    if extensions.len() > formats.len() {
+        return Err(AppError::ExtensionCount {
+            extensions: extensions.len(),
+            formats: formats.len(),
+        });
+    }
+    if extensions.len() == formats.len() {
+        return Ok(formats
+            .iter()
+            .copied()
+            .zip(extensions.iter().copied())
+            .collect());
+    }
+
+    if !extensions.is_empty() {
+        warn!(
+            "ignoring --output-file-extension, {} extensions were passed for {} formats, the formats are written with their conventional extensions",
+            extensions.len(),
+            formats.len()
+        );
+    }
+    Ok(formats.iter().map(|f| (*f, f.extension())).collect())
+}
+
+/// The formats and extensions the playlist `p` is written in, its own format with the format's
+/// conventional extension if it has one.
+fn playlist_outputs<'a>(
+    p: &Playlist,
+    outputs: &[(PlaylistFormat, &'a str)],
+) -> Vec<(PlaylistFormat, &'a str)> {
+    match p.format_override() {
+        Some(f) => vec![(f, f.extension())],
+        None => outputs.to_vec(),
+    }
+}
+
+/// An output file extension without a leading dot, which can't be empty or contain a path
+/// separator.
+fn output_extension(s: &str) -> Result<String, String> {
+    let extension = s.strip_prefix('.').unwrap_or(s);
+    if extension.is_empty() {
+        return Err("the extension can't be empty".into());
+    }
+    if extension.contains(['/', '\\']) {
+        return Err(format!("the extension can't contain a path separator: {s}"));
+    }
+    Ok(extension.to_string())
+}
+
+/// Expands a leading `~` and `$VAR` or `${VAR}` environment variables in a path argument.
+fn expand_path(s: &str) -> Result<String, String> {
+    shellexpand::full(s)
+        .map(|p| p.into_owned())
+        .map_err(|e| format!("couldn't expand ${}: {}", e.var_name, e.cause))
+}
+
+/// A duration like `90s`, `30m`, `24h`, `7d` or `2w`.
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(split);
+    let count: u64 = count
+        .parse()
+        .map_err(|_| format!("expected a number followed by a unit like 24h: {s}"))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "unknown unit '{unit}', expected one of s, m, h, d or w"
+            ))
+        }
+    };
+    Ok(Duration::from_secs(count.saturating_mul(unit_secs)))
+}
+
+/// A comma separated list of playlist formats, or all of them for `all`.
+fn playlist_formats(s: &str) -> Result<Vec<PlaylistFormat>, String> {
+    if s == "all" {
+        return Ok(PlaylistFormat::ALL.to_vec());
+    }
+    let mut formats = Vec::new();
+    for f in s.split(',') {
+        let format = f.trim().parse().map_err(|e: &str| format!("{e}: {f}"))?;
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+    Ok(formats)
+}
+
+/// Like [`expand_path`], but also makes the path absolute without accessing the file system.
+fn absolute_path(s: &str) -> Result<PathBuf, String> {
+    let path = expand_path(s)?;
+    std::path::absolute(&path).map_err(|e| e.to_string())
+}
+
+/// Fills in the `<name>` and `<date>` placeholders of an output name template, and replaces
+/// characters that aren't allowed in file names on common file systems.
+fn output_name(template: &str, name: &str, date: &str) -> String {
+    template
+        .replace("<date>", date)
+        .replace("<name>", name)
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// The current UTC date as `YYYY-MM-DD`.
+fn utc_date() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    // converts the days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(r) if (0.0..=1.0).contains(&r) => Ok(r),
+        Ok(_) => Err("Value must be between 0 and 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{question} [y/N] ");
+    if std::io::stdout().flush().is_err() {
+        return false;
+    }
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim(), "y" | "Y" | "yes" | "Yes")
+}
+
+/// What to do with playlists that contain no songs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OnEmpty {
+    Skip,
+    Write,
+    Error,
+}
+
+impl FromStr for OnEmpty {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnEmpty::Skip),
+            "write" => Ok(OnEmpty::Write),
+            "error" => Ok(OnEmpty::Error),
+            _ => Err("Unknown empty playlist action"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_are_paired_by_position() {
+        let formats = [PlaylistFormat::M3u, PlaylistFormat::Xspf];
+        let outputs = output_extensions(&formats, &["m3u8", "xml"]).unwrap();
+        assert_eq!(
+            outputs,
+            [(PlaylistFormat::M3u, "m3u8"), (PlaylistFormat::Xspf, "xml")]
+        );
+    }
+
+    #[test]
+    fn fewer_extensions_fall_back_to_conventional_ones() {
+        let formats = [PlaylistFormat::M3u, PlaylistFormat::Xspf];
+        let outputs = output_extensions(&formats, &["m3u8"]).unwrap();
+        assert_eq!(
+            outputs,
+            [(PlaylistFormat::M3u, "m3u"), (PlaylistFormat::Xspf, "xspf")]
+        );
+        assert_eq!(output_extensions(&formats, &[]).unwrap(), outputs);
+    }
+
+    #[test]
+    fn more_extensions_than_formats_are_rejected() {
+        let formats = [PlaylistFormat::M3u, PlaylistFormat::Xspf];
+        let result = output_extensions(&formats, &["m3u", "xspf", "pls"]);
+        assert!(matches!(
+            result,
+            Err(AppError::ExtensionCount {
+                extensions: 3,
+                formats: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn all_formats_with_their_extensions() {
+        let formats = playlist_formats("all").unwrap();
+        assert_eq!(formats, PlaylistFormat::ALL);
+
+        let outputs = output_extensions(&formats, &[]).unwrap();
+        let expected = [
+            (PlaylistFormat::M3u, "m3u"),
+            (PlaylistFormat::Extm3u, "m3u8"),
+            (PlaylistFormat::Pls, "pls"),
+            (PlaylistFormat::Xspf, "xspf"),
+            (PlaylistFormat::Wpl, "wpl"),
+            (PlaylistFormat::Json, "json"),
+            (PlaylistFormat::Csv, "csv"),
+            (PlaylistFormat::Cue, "cue"),
+        ];
+        assert_eq!(outputs, expected);
+        let extensions: BTreeSet<&str> = outputs.iter().map(|(_, e)| *e).collect();
+        assert_eq!(extensions.len(), outputs.len());
+    }
+
+    #[test]
+    fn format_list_drops_duplicates() {
+        let formats = playlist_formats("m3u, xspf,m3u").unwrap();
+        assert_eq!(formats, [PlaylistFormat::M3u, PlaylistFormat::Xspf]);
+        assert!(playlist_formats("m3u,mp3").is_err());
+    }
+
+    #[test]
+    fn output_extension_leading_dot_is_removed() {
+        assert_eq!(output_extension(".m3u").unwrap(), "m3u");
+        assert_eq!(output_extension("m3u").unwrap(), "m3u");
+        assert_eq!(output_extension("tar.m3u").unwrap(), "tar.m3u");
+    }
+
+    #[test]
+    fn output_extension_empty_is_rejected() {
+        assert!(output_extension("").is_err());
+        assert!(output_extension(".").is_err());
+    }
+
+    #[test]
+    fn output_extension_path_separator_is_rejected() {
+        assert!(output_extension("m3u/x").is_err());
+        assert!(output_extension("..\\m3u").is_err());
+    }
+
+    #[test]
+    fn output_name_template() {
+        let file_name = output_name("loc_<name>", "Roadtrip", "2026-10-14");
+        let playlist = Playlist::new("Roadtrip".into(), Vec::new()).with_file_name(file_name);
+        let path = playlist.file_path(Path::new("out"), "m3u");
+        assert_eq!(path, Path::new("out/loc_Roadtrip.m3u"));
+    }
+
+    #[test]
+    fn output_name_date_and_illegal_characters() {
+        assert_eq!(output_name("<name>", "Roadtrip", "2026-10-14"), "Roadtrip");
+        assert_eq!(
+            output_name("<date> <name>", "AC/DC: Best?", "2026-10-14"),
+            "2026-10-14 AC_DC_ Best_"
+        );
+    }
+
+    #[test]
+    fn quiet_only_logs_errors() {
+        let level = log_level(true, 0);
+        assert!(Level::Error <= level);
+        assert!(Level::Warn > level && Level::Info > level);
+        assert_eq!(log_level(true, 2), level);
+    }
+
+    #[test]
+    fn verbosity_levels() {
+        assert_eq!(log_level(false, 0), LevelFilter::Info);
+        assert_eq!(log_level(false, 1), LevelFilter::Debug);
+        assert_eq!(log_level(false, 3), LevelFilter::Trace);
+    }
 }
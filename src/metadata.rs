@@ -1,30 +1,501 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
 use std::path::Path;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
 
-use id3::TagLike;
+use lofty::config::ParseOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::properties::FileProperties;
+use lofty::tag::{ItemKey, Tag};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default)]
+/// The separator between multiple artists of a song, if no other one is configured.
+pub const ARTIST_SEPARATOR: &str = "; ";
+
+/// The file name of the metadata cache inside the output directory.
+pub const CACHE_FILE_NAME: &str = ".playlist-localizer-cache.json";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct SongMetadata {
     pub title: String,
-    pub artist: String,
-    pub duration: u64,
+    /// All artist values of the tag, in their original order.
+    pub artists: Vec<String>,
+    pub album: String,
+    /// The track number, 0 if unknown.
+    pub track: u32,
+    /// The duration in milliseconds, 0 if unknown. Formats that count in seconds use
+    /// [`SongMetadata::as_secs`].
+    pub duration_ms: u64,
+    /// The genre of the tag, or of the genre folder the song is in, see
+    /// `FormatOptions::genre_from_depth`.
+    #[serde(default)]
+    pub genre: String,
 }
 
 impl<T: AsRef<Path>> From<T> for SongMetadata {
     fn from(path: T) -> Self {
-        if let Ok(tag) = id3::Tag::read_from_path(path.as_ref()) {
-            Self {
-                title: tag.title().unwrap_or("").to_string(),
-                artist: tag.artist().unwrap_or("").to_string(),
-                duration: tag.duration().unwrap_or(0) as u64 / 1000,
+        Self::read(path.as_ref(), true)
+    }
+}
+
+impl SongMetadata {
+    /// Reads the tags of the song at `path`. The duration is only read from the audio properties
+    /// if `read_duration` is set, which requires parsing more of the file.
+    pub fn read(path: &Path, read_duration: bool) -> Self {
+        let options = ParseOptions::new().read_properties(read_duration);
+        let probe = Probe::open(path).and_then(|p| match p.file_type() {
+            Some(_) => Ok(p),
+            // extensionless files indexed with --sniff
+            None => Ok(p.guess_file_type()?),
+        });
+        let Ok(file) = probe.and_then(|p| p.options(options).read()) else {
+            return Self::default();
+        };
+        let duration_ms = match read_duration {
+            true => stream_duration(path, file.properties()),
+            false => 0,
+        };
+        let Some(tag) = file.primary_tag().or_else(|| file.first_tag()) else {
+            return Self {
+                duration_ms,
+                ..Default::default()
+            };
+        };
+
+        Self {
+            title: tag.title().unwrap_or_default().into_owned(),
+            artists: artists(tag),
+            album: tag.album().unwrap_or_default().into_owned(),
+            track: tag.track().unwrap_or_default(),
+            duration_ms,
+            genre: tag.genre().unwrap_or_default().into_owned(),
+        }
+    }
+}
+
+impl SongMetadata {
+    /// The artists joined by [`ARTIST_SEPARATOR`].
+    pub fn artist(&self) -> String {
+        self.joined_artists(ARTIST_SEPARATOR)
+    }
+
+    pub fn joined_artists(&self, separator: &str) -> String {
+        self.artists.join(separator)
+    }
+
+    /// The duration in whole seconds, 0 if unknown.
+    pub fn as_secs(&self) -> u64 {
+        self.duration_ms / 1000
+    }
+
+    /// The duration in milliseconds, 0 if unknown.
+    pub fn as_millis(&self) -> u64 {
+        self.duration_ms
+    }
+}
+
+/// The values of the multi-value artists field, or otherwise the artist field. Some tag formats
+/// store multiple values in a single one separated by null characters.
+fn artists(tag: &Tag) -> Vec<String> {
+    let mut values = tag.get_strings(ItemKey::TrackArtists).peekable();
+    let values = match values.peek() {
+        Some(_) => values.collect::<Vec<_>>(),
+        None => tag.get_strings(ItemKey::TrackArtist).collect(),
+    };
+
+    values
+        .into_iter()
+        .flat_map(|v| v.split('\0'))
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The duration of the audio stream in milliseconds. If the properties don't contain it, it's
+/// estimated from the size of the file and the bitrate.
+fn stream_duration(path: &Path, properties: &FileProperties) -> u64 {
+    let duration = properties.duration().as_millis() as u64;
+    if duration > 0 {
+        return duration;
+    }
+
+    let bitrate = properties
+        .audio_bitrate()
+        .or(properties.overall_bitrate())
+        .filter(|b| *b > 0);
+    match (bitrate, fs::metadata(path)) {
+        (Some(kbps), Ok(m)) => m.len() * 8 / kbps as u64,
+        _ => 0,
+    }
+}
+
+/// A track of a cue sheet embedded in a flac file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CueTrack {
+    pub number: u8,
+    /// The start of the track's `INDEX 01` in seconds.
+    pub start: u64,
+    /// The duration until the next track or the end of the file in seconds.
+    pub duration: u64,
+}
+
+/// The tracks of the `CUESHEET` metadata block of the flac file at `path`, empty if it isn't a
+/// flac file or doesn't contain one.
+pub fn embedded_cue_tracks(path: &Path) -> Vec<CueTrack> {
+    read_cue_tracks(path).unwrap_or_default()
+}
+
+fn read_cue_tracks(path: &Path) -> Option<Vec<CueTrack>> {
+    const STREAMINFO: u8 = 0;
+    const CUESHEET: u8 = 5;
+
+    let mut file = io::BufReader::new(fs::File::open(path).ok()?);
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic).ok()?;
+    if &magic != b"fLaC" {
+        return None;
+    }
+
+    let mut sample_rate = None;
+    loop {
+        let mut header = [0; 4];
+        file.read_exact(&mut header).ok()?;
+        let (last, block_type) = (header[0] & 0x80 != 0, header[0] & 0x7f);
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+        match block_type {
+            STREAMINFO | CUESHEET => {
+                let mut block = vec![0; len as usize];
+                file.read_exact(&mut block).ok()?;
+                if block_type == STREAMINFO {
+                    let rate = block.get(10..13)?;
+                    let rate = u32::from_be_bytes([0, rate[0], rate[1], rate[2]]) >> 4;
+                    sample_rate = Some(rate as u64).filter(|r| *r > 0);
+                } else {
+                    return parse_cue_sheet(&block, sample_rate?);
+                }
+            }
+            _ => {
+                file.seek_relative(len as i64).ok()?;
             }
-        } else if let Ok(mut tag) = mp4ameta::Tag::read_from_path(path.as_ref()) {
-            Self {
-                title: tag.take_title().unwrap_or_default(),
-                artist: tag.take_artist().unwrap_or_default(),
-                duration: tag.duration().map(|d| d.as_secs()).unwrap_or(0),
+        }
+        if last {
+            return None;
+        }
+    }
+}
+
+/// Parses the tracks of a `CUESHEET` block, see the flac format specification.
+fn parse_cue_sheet(block: &[u8], sample_rate: u64) -> Option<Vec<CueTrack>> {
+    // the catalog number, lead-in samples, the cd flag and reserved bytes
+    let mut pos = 128 + 8 + 259;
+    let track_count = *block.get(pos)?;
+    pos += 1;
+
+    // the start sample and number of the tracks, including the lead-out track
+    let mut starts: Vec<(u64, u8)> = Vec::with_capacity(track_count as usize);
+    for _ in 0..track_count {
+        let offset = u64::from_be_bytes(block.get(pos..pos + 8)?.try_into().ok()?);
+        let number = *block.get(pos + 8)?;
+        let index_count = *block.get(pos + 35)? as usize;
+        pos += 36;
+
+        // the indexes are relative to the track, `INDEX 01` is where it actually starts
+        let mut start = offset;
+        for i in 0..index_count {
+            let index = block.get(pos + 12 * i..pos + 12 * i + 9)?;
+            if index[8] == 1 {
+                start = offset + u64::from_be_bytes(index[..8].try_into().ok()?);
             }
-        } else {
-            Self::default()
+        }
+        pos += 12 * index_count;
+        starts.push((start, number));
+    }
+
+    let tracks = starts
+        .windows(2)
+        .map(|w| CueTrack {
+            number: w[0].1,
+            start: w[0].0 / sample_rate,
+            duration: w[1].0.saturating_sub(w[0].0) / sample_rate,
+        })
+        .collect();
+    Some(tracks)
+}
+
+/// Caches the metadata of songs on disk, so tags are only read again if a file has changed.
+#[derive(Debug, Default)]
+pub struct MetadataCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    size: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(Self {
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            size: metadata.len(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    stamp: FileStamp,
+    metadata: SongMetadata,
+    /// Whether the duration wasn't read, because of `--no-decode-duration`.
+    #[serde(default)]
+    without_duration: bool,
+}
+
+impl MetadataCache {
+    /// Loads the cache at `path`, a missing or invalid cache file results in an empty cache.
+    pub fn load(path: &Path) -> Self {
+        let entries = fs::read_to_string(path)
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+
+        Self {
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let content = serde_json::to_string(&*entries)?;
+        fs::write(path, content)
+    }
+
+    /// Returns the cached metadata of the song at `path`, or reads it if the file's modification
+    /// time or size changed since it was cached. Entries cached without a duration are read again
+    /// if `read_duration` is set.
+    pub fn get_or_read(&self, path: &Path, read_duration: bool) -> SongMetadata {
+        let (Some(key), Some(stamp)) = (path.to_str(), FileStamp::of(path)) else {
+            return SongMetadata::read(path, read_duration);
+        };
+
+        let cached = {
+            let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            entries
+                .get(key)
+                .filter(|e| e.stamp == stamp && !(e.without_duration && read_duration))
+                .cloned()
+        };
+        if let Some(entry) = cached {
+            return entry.metadata;
+        }
+
+        let metadata = SongMetadata::read(path, read_duration);
+        self.insert_entry(key, stamp, metadata.clone(), !read_duration);
+        metadata
+    }
+
+    /// Caches the `metadata` of the song at `path` as if it was read from the file, until the
+    /// file changes. Songs that don't exist or whose path isn't valid unicode aren't cached.
+    pub fn insert(&self, path: &Path, metadata: SongMetadata) {
+        if let (Some(key), Some(stamp)) = (path.to_str(), FileStamp::of(path)) {
+            self.insert_entry(key, stamp, metadata, false);
+        }
+    }
+
+    fn insert_entry(
+        &self,
+        key: &str,
+        stamp: FileStamp,
+        metadata: SongMetadata,
+        without_duration: bool,
+    ) {
+        let entry = CacheEntry {
+            stamp,
+            metadata,
+            without_duration,
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.insert(key.to_string(), entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lofty::file::FileType;
+
+    use super::*;
+    use crate::MUSIC_EXTENSIONS;
+
+    /// A wav file with one second of 8 kHz mono silence and a RIFF INFO chunk with the `info`
+    /// fields, like `INAM` for the title.
+    fn wav(info: &[(&[u8; 4], &str)]) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.extend(1u16.to_le_bytes()); // pcm
+        fmt.extend(1u16.to_le_bytes()); // channels
+        fmt.extend(8000u32.to_le_bytes()); // sample rate
+        fmt.extend(8000u32.to_le_bytes()); // byte rate
+        fmt.extend(1u16.to_le_bytes()); // block align
+        fmt.extend(8u16.to_le_bytes()); // bits per sample
+
+        let mut list = b"INFO".to_vec();
+        for (id, value) in info {
+            let mut value = value.as_bytes().to_vec();
+            value.push(0);
+            list.extend(riff_chunk(id, &value));
+        }
+
+        let mut riff = b"WAVE".to_vec();
+        riff.extend(riff_chunk(b"fmt ", &fmt));
+        riff.extend(riff_chunk(b"data", &[0x80; 8000]));
+        riff.extend(riff_chunk(b"LIST", &list));
+        riff_chunk(b"RIFF", &riff)
+    }
+
+    fn riff_chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// A flac file with the stream info of two seconds of 44.1 kHz stereo audio and a vorbis
+    /// comment block with the `comments`, but no audio frames.
+    fn flac(comments: &[&str]) -> Vec<u8> {
+        let mut stream_info = Vec::new();
+        stream_info.extend(4096u16.to_be_bytes()); // min and max block size
+        stream_info.extend(4096u16.to_be_bytes());
+        stream_info.extend([0; 6]); // min and max frame size
+                                    // 20 bits sample rate, 3 bits channels - 1, 5 bits bits per sample - 1, 36 bits samples
+        let packed = (44_100u64 << 44) | (1 << 41) | (15 << 36) | 88_200;
+        stream_info.extend(packed.to_be_bytes());
+        stream_info.extend([0; 16]); // md5
+
+        let vendor = "test";
+        let mut vorbis_comment = (vendor.len() as u32).to_le_bytes().to_vec();
+        vorbis_comment.extend(vendor.as_bytes());
+        vorbis_comment.extend((comments.len() as u32).to_le_bytes());
+        for c in comments {
+            vorbis_comment.extend((c.len() as u32).to_le_bytes());
+            vorbis_comment.extend(c.as_bytes());
+        }
+
+        let mut file = b"fLaC".to_vec();
+        file.extend(flac_block(0, false, &stream_info));
+        file.extend(flac_block(4, true, &vorbis_comment));
+        file
+    }
+
+    fn flac_block(block_type: u8, last: bool, data: &[u8]) -> Vec<u8> {
+        let len = (data.len() as u32).to_be_bytes();
+        let mut block = vec![block_type | (last as u8) << 7, len[1], len[2], len[3]];
+        block.extend(data);
+        block
+    }
+
+    /// An mp3 file with an id3v2.3 tag with the text `frames`, like `TIT2` for the title, and
+    /// silent 128 kbps frames.
+    fn mp3(frames: &[(&[u8; 4], &str)]) -> Vec<u8> {
+        let mut tag = Vec::new();
+        for (id, value) in frames {
+            tag.extend(*id);
+            tag.extend((value.len() as u32 + 1).to_be_bytes());
+            tag.extend([0, 0, 0]); // flags and the latin-1 encoding
+            tag.extend(value.as_bytes());
+        }
+
+        let size = tag.len() as u32;
+        let syncsafe = [size >> 21, size >> 14, size >> 7, size].map(|b| (b & 0x7f) as u8);
+        let mut file = b"ID3\x03\x00\x00".to_vec();
+        file.extend(syncsafe);
+        file.extend(tag);
+        // mpeg 1 layer 3, 128 kbps, 44.1 kHz, 417 bytes per frame
+        for _ in 0..40 {
+            file.extend([0xff, 0xfb, 0x90, 0x64]);
+            file.extend([0; 413]);
+        }
+        file
+    }
+
+    fn read_fixture(name: &str, contents: &[u8], read_duration: bool) -> SongMetadata {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        SongMetadata::read(&path, read_duration)
+    }
+
+    #[test]
+    fn wav_riff_info() {
+        let contents = wav(&[(b"INAM", "Title"), (b"IART", "Artist"), (b"IPRD", "Album")]);
+        let song_metadata = read_fixture("song.wav", &contents, true);
+        assert_eq!(song_metadata.title, "Title");
+        assert_eq!(song_metadata.artists, ["Artist"]);
+        assert_eq!(song_metadata.album, "Album");
+        assert_eq!(song_metadata.duration_ms, 1000);
+    }
+
+    #[test]
+    fn flac_vorbis_comment() {
+        let contents = flac(&["TITLE=Title", "ARTIST=A", "ARTIST=B", "TRACKNUMBER=3"]);
+        let song_metadata = read_fixture("song.flac", &contents, true);
+        assert_eq!(song_metadata.title, "Title");
+        assert_eq!(song_metadata.artists, ["A", "B"]);
+        assert_eq!(song_metadata.track, 3);
+        assert_eq!(song_metadata.duration_ms, 2000);
+    }
+
+    #[test]
+    fn mp3_id3v2() {
+        let contents = mp3(&[(b"TIT2", "Title"), (b"TPE1", "Artist"), (b"TCON", "Rock")]);
+        let song_metadata = read_fixture("song.mp3", &contents, true);
+        assert_eq!(song_metadata.title, "Title");
+        assert_eq!(song_metadata.artists, ["Artist"]);
+        assert_eq!(song_metadata.genre, "Rock");
+        assert!(song_metadata.duration_ms > 0);
+    }
+
+    #[test]
+    fn read_duration_is_optional() {
+        let contents = wav(&[(b"INAM", "Title")]);
+        let song_metadata = read_fixture("song.wav", &contents, false);
+        assert_eq!(song_metadata.title, "Title");
+        assert_eq!(song_metadata.duration_ms, 0);
+    }
+
+    #[test]
+    fn unreadable_files_have_no_metadata() {
+        let song_metadata = read_fixture("song.flac", b"not a flac file", true);
+        assert!(song_metadata.title.is_empty());
+        assert_eq!(song_metadata.duration_ms, 0);
+    }
+
+    #[test]
+    fn truncated_stream_info_has_no_cue_tracks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        fs::write(&path, b"fLaC\x80\x00\x00\x04abcd").unwrap();
+        assert_eq!(embedded_cue_tracks(&path), []);
+        fs::write(&path, flac(&["TITLE=Title"])).unwrap();
+        assert_eq!(embedded_cue_tracks(&path), []);
+    }
+
+    #[test]
+    fn every_music_extension_is_read_by_lofty() {
+        for extension in MUSIC_EXTENSIONS {
+            let file_type = FileType::from_ext(extension);
+            assert!(file_type.is_some(), "{extension}");
         }
     }
 }
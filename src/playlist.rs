@@ -1,25 +1,349 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::OsString;
 use std::fs;
-use std::path::Path;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
-use crate::metadata::SongMetadata;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::{
+    embedded_cue_tracks, CueTrack, MetadataCache, SongMetadata, ARTIST_SEPARATOR,
+};
+use crate::{canonicalize, ExtInf, PlaylistInputFormat};
 
 const EXTM3U_HEADER: &str = "#EXTM3U";
-const EXTM3U_SONG_PATTERN: &str = "
-#EXTINF:<duration>,<artist> - <title>
-<path>";
+const PLS_HEADER: &str = "[playlist]";
+const XSPF_HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+"#;
+const WPL_HEADER: &str = r#"<?wpl version="1.0"?>
+<smil>
+"#;
+/// The default template of the information after `#EXTINF:`.
+pub const EXTINF_TEMPLATE: &str = "<duration>,<artist> - <title>";
 
 #[derive(Debug)]
 pub struct Playlist<'a> {
     name: String,
+    /// The file name the playlist is written to without the extension, the name if it's not set.
+    file_name: Option<String>,
+    /// The directory of the playlist relative to the output dir, empty for a flat layout.
+    dir: PathBuf,
+    /// The format this playlist is written in instead of the ones passed to the writer.
+    format: Option<PlaylistFormat>,
     songs: Vec<&'a Path>,
+    /// The source paths of songs that weren't found.
+    unmatched: Vec<PathBuf>,
+    /// The lines of the source playlist, in their original order.
+    source: Vec<SourceLine>,
+}
+
+/// A [`Playlist`] which owns the paths of its songs, so it can be serialized and loaded again
+/// without the music index.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OwnedPlaylist {
+    pub name: String,
+    /// The file name the playlist is written to without the extension, the name if it's not set.
+    #[serde(default)]
+    pub file_name: Option<String>,
+    /// The directory of the playlist relative to the output dir, empty for a flat layout.
+    pub dir: PathBuf,
+    /// The format this playlist is written in instead of the ones passed to the writer.
+    #[serde(default)]
+    pub format: Option<PlaylistFormat>,
+    pub songs: Vec<PathBuf>,
+    /// The source paths of songs that weren't found.
+    pub unmatched: Vec<PathBuf>,
+    /// The lines of the source playlist, in their original order.
+    pub source: Vec<SourceLine>,
+}
+
+impl From<Playlist<'_>> for OwnedPlaylist {
+    fn from(playlist: Playlist<'_>) -> Self {
+        OwnedPlaylist {
+            name: playlist.name,
+            file_name: playlist.file_name,
+            dir: playlist.dir,
+            format: playlist.format,
+            songs: playlist.songs.into_iter().map(Path::to_path_buf).collect(),
+            unmatched: playlist.unmatched,
+            source: playlist.source,
+        }
+    }
 }
 
-#[derive(Clone, Copy, Default, PartialEq, Eq)]
+impl OwnedPlaylist {
+    /// A playlist borrowing the songs of this one, to format or write it.
+    pub fn playlist(&self) -> Playlist<'_> {
+        Playlist {
+            name: self.name.clone(),
+            file_name: self.file_name.clone(),
+            dir: self.dir.clone(),
+            format: self.format,
+            songs: self.songs.iter().map(PathBuf::as_path).collect(),
+            unmatched: self.unmatched.clone(),
+            source: self.source.clone(),
+        }
+    }
+}
+
+/// A line of the source playlist.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SourceLine {
+    /// A comment or directive, like `#EXTGRP:` or `#EXTINF:`.
+    Comment(String),
+    /// The index of a localized song.
+    Song(usize),
+    /// The source path of a song that wasn't found.
+    NotFound(PathBuf),
+    /// An http or https URL, which is written verbatim.
+    Url(String),
+    /// An `#EXTIMG:` directive with the local path of its image.
+    Image(PathBuf),
+}
+
+#[derive(Serialize)]
+struct JsonPlaylist<'a> {
+    name: &'a str,
+    songs: Vec<JsonSong>,
+}
+
+#[derive(Serialize)]
+struct JsonSong {
+    /// The path, lossily converted if it isn't valid UTF-8.
+    path: String,
+    path_valid: bool,
+    title: String,
+    artist: String,
+    duration_secs: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    genre: String,
+}
+
+/// Options controlling how a playlist is formatted.
+#[derive(Clone, Debug, Default)]
+pub struct FormatOptions {
+    /// Prepend a `# 0001` style comment to each song.
+    pub number_entries: bool,
+    /// Resolve symlinks in the written song paths.
+    pub canonical_paths: bool,
+    /// Write song paths relative to the directory of the playlist file.
+    pub relative: bool,
+    /// Write a `# base: <dir>` comment with the directory the song paths are relative to.
+    pub base_comment: bool,
+    /// The columns of the csv format.
+    pub csv_columns: Vec<CsvColumn>,
+    /// Write a header row in the csv format.
+    pub csv_header: bool,
+    /// Indent the json format over multiple lines instead of writing it on a single line.
+    pub json_pretty: bool,
+    /// The absolute directory relative paths are computed from, `write_to` sets this to the
+    /// directory the playlist is written to.
+    pub base: Option<PathBuf>,
+    /// The template of the information after `#EXTINF:`, see [`EXTINF_TEMPLATE`].
+    pub extinf_template: Option<String>,
+    /// Keep the comments and directives of the source playlist in the extm3u format, and write a
+    /// `# not found: <path>` comment for songs that weren't found.
+    pub preserve_comments: bool,
+    /// Look up song metadata in this cache before reading the tags.
+    pub metadata_cache: Option<Arc<MetadataCache>>,
+    /// Write song paths that aren't valid unicode with replacement characters instead of
+    /// skipping them, in the formats that store plain paths.
+    pub lossy_paths: bool,
+    pub line_ending: LineEnding,
+    /// Read the tags of songs for the extm3u format, even if the source playlist had an
+    /// `#EXTINF` line for them.
+    pub prefer_file_tags: bool,
+    /// Remove this prefix from the written song paths, ones that don't start with it are left
+    /// unchanged.
+    pub strip_prefix: Option<PathBuf>,
+    /// Write song paths relative to the one of these music dirs that contains them, wherever
+    /// the playlist is written. Songs outside of them are left unchanged.
+    pub music_roots: Vec<PathBuf>,
+    /// Prepend this prefix to the written song paths, after they were made relative or had a
+    /// prefix stripped.
+    pub prepend_prefix: Option<PathBuf>,
+    /// Don't read the duration of songs, which requires parsing the audio stream.
+    pub no_decode_duration: bool,
+    /// The separator between multiple artists of a song, [`ARTIST_SEPARATOR`] if it isn't set.
+    pub artist_separator: Option<String>,
+    /// What `write_to` does if the playlist file already exists.
+    pub overwrite: Overwrite,
+    /// The file names of cover art next to the songs, in the order they are looked for. Art is
+    /// written in the extm3u and xspf formats, if this isn't empty.
+    pub art_names: Vec<String>,
+    /// Write a `<path>#track=<n>` entry for every track of a cue sheet embedded in a flac file,
+    /// in the m3u and extm3u formats, and a track of the file at its offset in the cue format.
+    pub split_embedded_cue: bool,
+    /// Use the name of the directory this many levels above a song as its genre, if its tag has
+    /// none. 1 is the directory containing the song.
+    pub genre_from_depth: Option<usize>,
+    /// Write a `# MISSING: <path>` comment in place of songs that weren't found, in the m3u and
+    /// extm3u formats.
+    pub keep_missing: bool,
+    /// Write only the file names of songs, after all other path transformations.
+    pub flatten: bool,
+}
+
+impl FormatOptions {
+    /// Applies the path transformations to a song's path before it's written.
+    fn output_path<'a>(&self, song: &'a Path) -> Cow<'a, Path> {
+        let mut path = Cow::Borrowed(song);
+        if self.canonical_paths {
+            if let Ok(p) = canonicalize(song) {
+                path = Cow::Owned(p);
+            }
+        }
+        if let (true, Some(base)) = (self.relative, &self.base) {
+            if let Some(p) = relative_path(&path, base) {
+                path = Cow::Owned(p);
+            }
+        }
+        if let Some(prefix) = &self.strip_prefix {
+            if let Ok(p) = path.strip_prefix(prefix) {
+                path = Cow::Owned(p.to_path_buf());
+            }
+        }
+        // nested music dirs are stripped by the innermost one
+        let root = (self.music_roots.iter())
+            .filter(|r| path.starts_with(r))
+            .max_by_key(|r| r.components().count());
+        if let Some(p) = root.and_then(|r| path.strip_prefix(r).ok()) {
+            path = Cow::Owned(p.to_path_buf());
+        }
+        if let Some(prefix) = &self.prepend_prefix {
+            // joining an absolute path would replace the prefix
+            let components = path
+                .components()
+                .filter(|c| !matches!(c, Component::Prefix(_) | Component::RootDir));
+            let mut prefixed = prefix.clone();
+            prefixed.extend(components);
+            path = Cow::Owned(prefixed);
+        }
+        if self.flatten {
+            if let Some(name) = path.file_name() {
+                path = Cow::Owned(PathBuf::from(name));
+            }
+        }
+
+        path
+    }
+
+    /// The local paths a song path `written` to a playlist in `dir` could refer to, reversing the
+    /// path transformations of `output_path` that can be reversed. Flattened paths can't be.
+    pub fn local_candidates(&self, written: &Path, dir: &Path) -> Vec<PathBuf> {
+        let mut path = written;
+        let mut prefixed = false;
+        if let Some(p) =
+            (self.prepend_prefix.as_ref()).and_then(|pre| written.strip_prefix(pre).ok())
+        {
+            path = p;
+            prefixed = true;
+        }
+        if path.is_absolute() {
+            return vec![path.to_path_buf()];
+        }
+
+        let mut candidates = vec![dir.join(path)];
+        // the root of absolute paths is removed when a prefix is prepended
+        if prefixed {
+            candidates.push(Path::new("/").join(path));
+        }
+        candidates.extend(self.strip_prefix.iter().map(|p| p.join(path)));
+        candidates.extend(self.music_roots.iter().map(|r| r.join(path)));
+        candidates
+    }
+
+    /// The tracks of the cue sheet embedded in the song, if it should be split into them. Empty
+    /// if there's only a single track.
+    fn cue_tracks(&self, song: &Path) -> Vec<CueTrack> {
+        if !self.split_embedded_cue {
+            return Vec::new();
+        }
+        let tracks = embedded_cue_tracks(song);
+        match tracks.len() {
+            0 | 1 => Vec::new(),
+            _ => tracks,
+        }
+    }
+
+    /// The first file of the `art_names` in the directory of the song.
+    fn find_art(&self, song: &Path) -> Option<PathBuf> {
+        let dir = song.parent()?;
+        self.art_names
+            .iter()
+            .map(|n| dir.join(n))
+            .find(|p| p.is_file())
+    }
+
+    /// The artists of a song joined by the artist separator.
+    fn artist(&self, song_metadata: &SongMetadata) -> String {
+        let separator = self.artist_separator.as_deref();
+        song_metadata.joined_artists(separator.unwrap_or(ARTIST_SEPARATOR))
+    }
+
+    /// Reads the metadata of a song, from the cache if there is one.
+    fn read_metadata(&self, song: &Path) -> SongMetadata {
+        let song_metadata = match self.metadata_cache.as_deref() {
+            Some(cache) => cache.get_or_read(song, !self.no_decode_duration),
+            None => SongMetadata::read(song, !self.no_decode_duration),
+        };
+        self.with_folder_genre(song, song_metadata)
+    }
+
+    /// Sets the genre of songs whose tag has none to the name of their genre folder.
+    fn with_folder_genre(&self, song: &Path, mut song_metadata: SongMetadata) -> SongMetadata {
+        if let (true, Some(depth)) = (song_metadata.genre.is_empty(), self.genre_from_depth) {
+            let folder = song.ancestors().nth(depth).and_then(Path::file_name);
+            if let Some(folder) = folder {
+                song_metadata.genre = folder.to_string_lossy().into_owned();
+            }
+        }
+        song_metadata
+    }
+
+    /// The output path of a song as a string. Paths that aren't valid unicode are replaced lossily
+    /// if `lossy_paths` is set, otherwise they are skipped with a warning.
+    fn output_str(&self, song: &Path) -> Option<String> {
+        let path = self.output_path(song);
+        if let Some(s) = path.to_str() {
+            return Some(s.to_string());
+        }
+
+        let lossy = path.to_string_lossy().into_owned();
+        if self.lossy_paths {
+            return Some(lossy);
+        }
+        log::warn!(
+            "skipping {lossy}, the path isn't valid unicode, pass --lossy-paths to write it anyway"
+        );
+        None
+    }
+
+    /// The base directory if song paths are written relative to it.
+    fn relative_base(&self) -> Option<&Path> {
+        self.base.as_deref().filter(|_| self.relative)
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PlaylistFormat {
     #[default]
     M3u,
     Extm3u,
+    Pls,
+    Xspf,
+    Wpl,
+    Json,
+    Csv,
+    /// A cue sheet with a track for every song, in a file of its own. The tracks of cue sheets
+    /// embedded in flac files share the file, see `FormatOptions::split_embedded_cue`.
+    Cue,
 }
 
 impl FromStr for PlaylistFormat {
@@ -29,60 +353,1707 @@ impl FromStr for PlaylistFormat {
         match s {
             "m3u" => Ok(PlaylistFormat::M3u),
             "extm3u" => Ok(PlaylistFormat::Extm3u),
+            "pls" => Ok(PlaylistFormat::Pls),
+            "xspf" => Ok(PlaylistFormat::Xspf),
+            "wpl" => Ok(PlaylistFormat::Wpl),
+            "json" => Ok(PlaylistFormat::Json),
+            "csv" => Ok(PlaylistFormat::Csv),
+            "cue" => Ok(PlaylistFormat::Cue),
             _ => Err("Unknown playlist format"),
         }
     }
 }
 
+impl PlaylistFormat {
+    pub const ALL: [PlaylistFormat; 8] = [
+        PlaylistFormat::M3u,
+        PlaylistFormat::Extm3u,
+        PlaylistFormat::Pls,
+        PlaylistFormat::Xspf,
+        PlaylistFormat::Wpl,
+        PlaylistFormat::Json,
+        PlaylistFormat::Csv,
+        PlaylistFormat::Cue,
+    ];
+
+    /// The file extension conventionally used for the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u => "m3u",
+            PlaylistFormat::Extm3u => "m3u8",
+            PlaylistFormat::Pls => "pls",
+            PlaylistFormat::Xspf => "xspf",
+            PlaylistFormat::Wpl => "wpl",
+            PlaylistFormat::Json => "json",
+            PlaylistFormat::Csv => "csv",
+            PlaylistFormat::Cue => "cue",
+        }
+    }
+
+    /// The format written playlists are read back in, `None` if they can't be read as a
+    /// playlist.
+    pub fn input_format(self) -> Option<PlaylistInputFormat> {
+        match self {
+            PlaylistFormat::M3u | PlaylistFormat::Extm3u => Some(PlaylistInputFormat::M3u),
+            PlaylistFormat::Pls => Some(PlaylistInputFormat::Pls),
+            PlaylistFormat::Wpl => Some(PlaylistInputFormat::Wpl),
+            PlaylistFormat::Cue => Some(PlaylistInputFormat::Cue),
+            PlaylistFormat::Xspf | PlaylistFormat::Json | PlaylistFormat::Csv => None,
+        }
+    }
+
+    /// Whether files with the `extension` would be read as another format, both m3u extensions
+    /// fit both m3u formats.
+    pub fn conflicts_with(self, extension: &str) -> bool {
+        let m3u = |f| matches!(f, PlaylistFormat::M3u | PlaylistFormat::Extm3u);
+        PlaylistFormat::ALL.iter().any(|&f| {
+            f != self && f.extension().eq_ignore_ascii_case(extension) && !(m3u(f) && m3u(self))
+        })
+    }
+}
+
+/// The order of the songs in the written playlists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// The order of the source playlist.
+    #[default]
+    None,
+    Path,
+    Title,
+    Artist,
+}
+
+impl FromStr for SortOrder {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SortOrder::None),
+            "path" => Ok(SortOrder::Path),
+            "title" => Ok(SortOrder::Title),
+            "artist" => Ok(SortOrder::Artist),
+            _ => Err("Unknown sort order"),
+        }
+    }
+}
+
+impl SortOrder {
+    /// Whether the metadata of the songs is needed to sort them.
+    pub fn needs_metadata(&self) -> bool {
+        matches!(self, SortOrder::Title | SortOrder::Artist)
+    }
+}
+
+/// What to do with an existing playlist file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overwrite {
+    #[default]
+    Always,
+    /// Skip the playlist with a warning.
+    Never,
+    /// Rename the existing file by appending `.bak` before writing.
+    Backup,
+}
+
+/// The line separator of the written playlists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        match cfg!(windows) {
+            true => LineEnding::Crlf,
+            false => LineEnding::Lf,
+        }
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            _ => Err("Unknown line ending"),
+        }
+    }
+}
+
+impl LineEnding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "lf",
+            LineEnding::Crlf => "crlf",
+        }
+    }
+
+    /// Replaces the `\n` line separators of `content`.
+    fn apply(&self, content: String) -> String {
+        match self {
+            LineEnding::Lf => content,
+            LineEnding::Crlf => content.replace('\n', "\r\n"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsvColumn {
+    Path,
+    Title,
+    Artist,
+    Album,
+    Duration,
+}
+
+impl FromStr for CsvColumn {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(CsvColumn::Path),
+            "title" => Ok(CsvColumn::Title),
+            "artist" => Ok(CsvColumn::Artist),
+            "album" => Ok(CsvColumn::Album),
+            "duration" => Ok(CsvColumn::Duration),
+            _ => Err("Unknown csv column"),
+        }
+    }
+}
+
+impl CsvColumn {
+    fn name(&self) -> &'static str {
+        match self {
+            CsvColumn::Path => "path",
+            CsvColumn::Title => "title",
+            CsvColumn::Artist => "artist",
+            CsvColumn::Album => "album",
+            CsvColumn::Duration => "duration",
+        }
+    }
+}
+
+/// Writes a playlist named `name` into `path` which references the files of all `playlists`
+/// relative to `path`.
+pub fn write_index(
+    path: &Path,
+    name: &str,
+    playlists: &[Playlist],
+    format: PlaylistFormat,
+    extension: &str,
+    line_ending: LineEnding,
+) -> io::Result<()> {
+    let file_names: Vec<PathBuf> = playlists
+        .iter()
+        .filter_map(|p| {
+            let file_path = p.file_path(path, extension);
+            file_path.strip_prefix(path).ok().map(PathBuf::from)
+        })
+        .collect();
+
+    let content = match format {
+        PlaylistFormat::Extm3u => {
+            let mut content = format!("{EXTM3U_HEADER}\n");
+            for (p, f) in playlists.iter().zip(file_names.iter()) {
+                let Some(f) = f.to_str() else {
+                    continue;
+                };
+                content.push_str(&format!("#EXTINF:-1,{}\n{}\n", p.name, f));
+            }
+            line_ending.apply(content)
+        }
+        _ => {
+            let songs = file_names.iter().map(PathBuf::as_path).collect();
+            let options = FormatOptions {
+                csv_columns: vec![CsvColumn::Path],
+                line_ending,
+                ..Default::default()
+            };
+            Playlist::new(name.to_string(), songs).format(format, &options)
+        }
+    };
+
+    // the extension is appended, so names that contain a dot are kept whole
+    let mut file_name = OsString::from(name);
+    if !extension.is_empty() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    write_atomic(&path.join(file_name), content)
+}
+
+/// Writes `content` to a temporary file next to `path` and renames it to `path`, so readers never
+/// see a partially written file. The rename replaces an existing file, also on windows.
+fn write_atomic(path: &Path, content: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let written = fs::File::create(&tmp_path).and_then(|mut f| {
+        f.write_all(content.as_ref())?;
+        f.sync_all()
+    });
+    let result = written.and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
 impl<'a> Playlist<'a> {
     pub fn new(name: String, songs: Vec<&'a Path>) -> Self {
-        Playlist { name, songs }
+        Playlist {
+            name,
+            file_name: None,
+            dir: PathBuf::new(),
+            format: None,
+            songs,
+            unmatched: Vec::new(),
+            source: Vec::new(),
+        }
     }
 
-    pub fn write_to(&mut self, path: &Path, format: PlaylistFormat, extension: &str) {
-        let file_path = path.join(&self.name).with_extension(extension);
+    pub fn with_source(mut self, source: Vec<SourceLine>) -> Self {
+        self.source = source;
+        self
+    }
 
-        let r = fs::write(
-            file_path,
-            match format {
-                PlaylistFormat::M3u => self.to_m3u(),
-                PlaylistFormat::Extm3u => self.to_extm3u(),
-            },
-        );
+    pub fn with_file_name(mut self, file_name: String) -> Self {
+        self.file_name = Some(file_name);
+        self
+    }
+
+    pub fn with_dir(mut self, dir: PathBuf) -> Self {
+        self.dir = dir;
+        self
+    }
+
+    /// Writes this playlist in `format` instead of the formats passed to the writer.
+    pub fn with_format(mut self, format: Option<PlaylistFormat>) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Replaces each song with the path returned by `f`, like the location it was exported to.
+    pub fn map_songs<'b>(self, f: impl FnMut(&'a Path) -> &'b Path) -> Playlist<'b> {
+        Playlist {
+            name: self.name,
+            file_name: self.file_name,
+            dir: self.dir,
+            format: self.format,
+            songs: self.songs.into_iter().map(f).collect(),
+            unmatched: self.unmatched,
+            source: self.source,
+        }
+    }
+
+    pub fn with_unmatched(mut self, unmatched: Vec<PathBuf>) -> Self {
+        self.unmatched = unmatched;
+        self
+    }
+
+    /// Concatenates the songs of all `playlists` in their order into one playlist named `name`.
+    pub fn merge(name: String, playlists: Vec<Playlist<'a>>) -> Self {
+        let mut merged = Playlist::new(name, Vec::new());
+        for p in playlists {
+            let offset = merged.songs.len();
+            merged
+                .source
+                .extend(p.lines().iter().cloned().map(|l| match l {
+                    SourceLine::Song(i) => SourceLine::Song(offset + i),
+                    l => l,
+                }));
+            merged.songs.extend(p.songs);
+            merged.unmatched.extend(p.unmatched);
+        }
+
+        merged
+    }
+
+    /// Splits the playlist into playlists of at most `size` songs named `<name>.1`, `<name>.2`
+    /// and so on. Lines that aren't songs go with the song after them. A playlist that isn't
+    /// longer than `size` is returned unchanged.
+    pub fn into_chunks(self, size: usize) -> Vec<Self> {
+        if self.songs.len() <= size || size == 0 {
+            return vec![self];
+        }
+
+        let count = self.songs.len().div_ceil(size);
+        let mut chunks: Vec<Self> = self
+            .songs
+            .chunks(size)
+            .enumerate()
+            .map(|(n, songs)| {
+                Playlist::new(format!("{}.{}", self.name, n + 1), songs.to_vec())
+                    .with_dir(self.dir.clone())
+                    .with_format(self.format)
+            })
+            .collect();
+
+        let mut chunk = count - 1;
+        let mut lines: Vec<Vec<SourceLine>> = vec![Vec::new(); count];
+        for line in self.lines().iter().rev() {
+            let line = match line {
+                SourceLine::Song(i) => {
+                    chunk = i / size;
+                    SourceLine::Song(i % size)
+                }
+                l => l.clone(),
+            };
+            lines[chunk].push(line);
+        }
+        for (c, mut l) in chunks.iter_mut().zip(lines) {
+            l.reverse();
+            c.source = l;
+        }
+        chunks[count - 1].unmatched = self.unmatched;
+
+        chunks
+    }
+
+    /// Removes songs that resolve to the same local path as a previous one, keeping the first
+    /// occurrence. Returns the number of removed songs.
+    pub fn dedup(&mut self) -> usize {
+        let mut seen = HashSet::new();
+        let mut indices = Vec::with_capacity(self.songs.len());
+        let mut songs = Vec::with_capacity(self.songs.len());
+        for s in self.songs.iter() {
+            if seen.insert(*s) {
+                indices.push(Some(songs.len()));
+                songs.push(*s);
+            } else {
+                indices.push(None);
+            }
+        }
+
+        let removed = self.songs.len() - songs.len();
+        self.songs = songs;
+
+        // the `#EXTINF` and `#EXTIMG` lines of a removed song would otherwise describe the next
+        // one, so the lines are walked backwards to know which song they belong to
+        let mut song_removed = false;
+        let mut source = Vec::with_capacity(self.source.len());
+        for mut line in std::mem::take(&mut self.source).into_iter().rev() {
+            match &mut line {
+                SourceLine::Song(i) => match indices[*i] {
+                    Some(new) => {
+                        *i = new;
+                        song_removed = false;
+                    }
+                    None => {
+                        song_removed = true;
+                        continue;
+                    }
+                },
+                SourceLine::Comment(c) if song_removed && c.starts_with("#EXTINF:") => continue,
+                SourceLine::Image(_) if song_removed => continue,
+                SourceLine::Comment(_) | SourceLine::Image(_) => (),
+                SourceLine::NotFound(_) | SourceLine::Url(_) => song_removed = false,
+            }
+            source.push(line);
+        }
+        source.reverse();
+        self.source = source;
+
+        removed
+    }
+
+    /// Sorts the songs, the comments before a song in the source playlist are moved with it.
+    /// Songs that weren't found and URLs are moved to the end.
+    pub fn sort(&mut self, order: SortOrder, options: &FormatOptions) {
+        let mut sorted: Vec<usize> = (0..self.songs.len()).collect();
+        match order {
+            SortOrder::None => return,
+            SortOrder::Path => sorted.sort_by_key(|i| self.songs[*i]),
+            SortOrder::Title | SortOrder::Artist => {
+                let keys: Vec<String> = self
+                    .metadata(options)
+                    .into_iter()
+                    .map(|m| match order {
+                        SortOrder::Title => m.title.to_lowercase(),
+                        _ => options.artist(&m).to_lowercase(),
+                    })
+                    .collect();
+                sorted.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+            }
+        }
+
+        let mut indices = vec![0; sorted.len()];
+        for (new, old) in sorted.iter().enumerate() {
+            indices[*old] = new;
+        }
+        self.songs = sorted.iter().map(|i| self.songs[*i]).collect();
+
+        // a block is a song, not found song or URL with the comments in front of it
+        let mut blocks: Vec<(usize, Vec<SourceLine>)> = Vec::new();
+        let mut block = Vec::new();
+        for mut line in self.source.drain(..) {
+            let key = match &mut line {
+                SourceLine::Comment(_) | SourceLine::Image(_) => {
+                    block.push(line);
+                    continue;
+                }
+                SourceLine::Song(i) => {
+                    *i = indices[*i];
+                    *i
+                }
+                SourceLine::NotFound(_) | SourceLine::Url(_) => usize::MAX,
+            };
+            block.push(line);
+            blocks.push((key, std::mem::take(&mut block)));
+        }
+        blocks.push((usize::MAX, block));
+
+        blocks.sort_by_key(|(key, _)| *key);
+        self.source = blocks.into_iter().flat_map(|(_, b)| b).collect();
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn format_override(&self) -> Option<PlaylistFormat> {
+        self.format
+    }
+
+    pub fn songs(&self) -> &[&'a Path] {
+        &self.songs
+    }
+
+    /// The local paths of the matched songs, in the order they are written.
+    pub fn entries(&self) -> impl Iterator<Item = &'a Path> + '_ {
+        self.songs.iter().copied()
+    }
+
+    /// The number of matched songs.
+    pub fn len(&self) -> usize {
+        self.songs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+
+    pub fn unmatched(&self) -> &[PathBuf] {
+        &self.unmatched
+    }
+
+    pub fn file_path(&self, path: &Path, extension: &str) -> PathBuf {
+        // the extension is appended, so names that contain a dot are kept whole
+        let mut file_name = OsString::from(self.file_name.as_ref().unwrap_or(&self.name));
+        if !extension.is_empty() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        path.join(&self.dir).join(file_name)
+    }
+
+    pub fn write_to(
+        &self,
+        path: &Path,
+        format: PlaylistFormat,
+        extension: &str,
+        options: &FormatOptions,
+    ) -> io::Result<()> {
+        let file_path = self.file_path(path, extension);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if file_path.exists() {
+            match options.overwrite {
+                Overwrite::Always => (),
+                Overwrite::Never => {
+                    log::warn!("skipping {}, it already exists", file_path.display());
+                    return Ok(());
+                }
+                Overwrite::Backup => {
+                    // the previous playlist is copied, so it stays in place until it's replaced
+                    let mut backup = file_path.clone().into_os_string();
+                    backup.push(".bak");
+                    fs::copy(&file_path, backup)?;
+                }
+            }
+        }
+
+        write_atomic(&file_path, self.format_in(path, format, options)?)
+    }
+
+    /// Formats the playlist as if it was written to the directory at `path`, which song paths are
+    /// made relative to if requested.
+    pub fn format_in(
+        &self,
+        path: &Path,
+        format: PlaylistFormat,
+        options: &FormatOptions,
+    ) -> io::Result<String> {
+        let options = self.options_in(path, options)?;
+        Ok(self.format(format, &options))
+    }
+
+    /// The song paths as they are written to a playlist in the directory at `path`, after the
+    /// path transformations.
+    pub fn output_paths_in(
+        &self,
+        path: &Path,
+        options: &FormatOptions,
+    ) -> io::Result<Vec<PathBuf>> {
+        let options = self.options_in(path, options)?;
+        let paths = self
+            .songs
+            .iter()
+            .map(|s| options.output_path(s).into_owned());
+        Ok(paths.collect())
+    }
+
+    /// The `options` with the base of relative paths set to the directory the playlist is written
+    /// to inside `path`.
+    fn options_in<'o>(
+        &self,
+        path: &Path,
+        options: &'o FormatOptions,
+    ) -> io::Result<Cow<'o, FormatOptions>> {
+        let mut options = Cow::Borrowed(options);
+        if options.relative {
+            let path = path.join(&self.dir);
+            let base = canonicalize(&path).or_else(|_| std::path::absolute(&path))?;
+            options.to_mut().base = Some(base);
+        }
+        Ok(options)
+    }
 
-        match r {
-            Ok(_) => (),
-            Err(e) => println!("Couldn't write playlist because:\n{:?}", e),
+    /// Prints the file that `write_to` would write and the number of songs, or every song path if
+    /// `verbose` is set.
+    pub fn preview(&self, path: &Path, extension: &str, verbose: bool) {
+        let file_path = self.file_path(path, extension);
+        println!("{}: {} songs", file_path.display(), self.songs.len());
+        if verbose {
+            for s in self.songs.iter() {
+                println!("  {}", s.display());
+            }
+        }
+    }
+
+    /// Reads the metadata of all songs in parallel, in the order of the songs.
+    fn metadata(&self, options: &FormatOptions) -> Vec<SongMetadata> {
+        self.songs
+            .par_iter()
+            .map(|p| options.read_metadata(p))
+            .collect()
+    }
+
+    /// Like [`Playlist::metadata`], but the `#EXTINF` line before a song in the source playlist
+    /// is used instead of its tags, unless `prefer_file_tags` is set.
+    fn extm3u_metadata(&self, options: &FormatOptions) -> Vec<SongMetadata> {
+        let mut source: Vec<Option<SongMetadata>> = vec![None; self.songs.len()];
+        if !options.prefer_file_tags {
+            let mut extinf = None;
+            for line in self.source.iter() {
+                match line {
+                    SourceLine::Comment(c) => {
+                        if let Some(info) = c.strip_prefix("#EXTINF:") {
+                            extinf = Some(info);
+                        }
+                    }
+                    SourceLine::Song(i) => {
+                        source[*i] = extinf.take().map(|e| {
+                            options.with_folder_genre(self.songs[*i], ExtInf::parse(e).metadata())
+                        });
+                    }
+                    SourceLine::NotFound(_) | SourceLine::Url(_) => extinf = None,
+                    SourceLine::Image(_) => (),
+                }
+            }
         }
+
+        source
+            .into_par_iter()
+            .zip(self.songs.par_iter())
+            .map(|(m, p)| m.unwrap_or_else(|| options.read_metadata(p)))
+            .collect()
+    }
+
+    pub fn format(&self, format: PlaylistFormat, options: &FormatOptions) -> String {
+        let content = match format {
+            PlaylistFormat::M3u => self.to_m3u(options),
+            PlaylistFormat::Extm3u => self.to_extm3u(options),
+            PlaylistFormat::Pls => self.to_pls(options),
+            PlaylistFormat::Xspf => self.to_xspf(options),
+            PlaylistFormat::Wpl => self.to_wpl(options),
+            PlaylistFormat::Json => self.to_json(options),
+            PlaylistFormat::Csv => self.to_csv(options),
+            PlaylistFormat::Cue => self.to_cue(options),
+        };
+
+        options.line_ending.apply(content)
     }
 
-    pub fn to_m3u(&self) -> String {
+    pub fn to_m3u(&self, options: &FormatOptions) -> String {
         let mut content = String::new();
+        if let (true, Some(base)) = (options.base_comment, options.relative_base()) {
+            content.push_str(&base_comment(base));
+            content.push('\n');
+        }
 
-        for p in &self.songs {
-            if let Some(s) = p.to_str() {
-                content.push_str(s);
-                content.push('\n');
+        for line in self.lines().iter() {
+            match line {
+                SourceLine::Song(i) => {
+                    if let Some(s) = options.output_str(self.songs[*i]) {
+                        if options.number_entries {
+                            content.push_str(&self.entry_number(*i));
+                            content.push('\n');
+                        }
+                        let tracks = options.cue_tracks(self.songs[*i]);
+                        if tracks.is_empty() {
+                            content.push_str(&s);
+                            content.push('\n');
+                        }
+                        for t in tracks {
+                            content.push_str(&format!("{s}#track={}\n", t.number));
+                        }
+                    }
+                }
+                SourceLine::Url(url) => {
+                    content.push_str(url);
+                    content.push('\n');
+                }
+                SourceLine::NotFound(p) if options.keep_missing => {
+                    content.push_str(&missing_comment(p));
+                }
+                SourceLine::Comment(_) | SourceLine::NotFound(_) | SourceLine::Image(_) => (),
             }
         }
 
         content
     }
 
-    pub fn to_extm3u(&self) -> String {
-        let mut content = String::from(EXTM3U_HEADER);
+    /// The songs and kept URLs in their original order, and comments if a source was recorded.
+    fn lines(&self) -> Cow<'_, [SourceLine]> {
+        match self.source.is_empty() {
+            true => Cow::Owned((0..self.songs.len()).map(SourceLine::Song).collect()),
+            false => Cow::Borrowed(&self.source),
+        }
+    }
 
-        for i in 0..self.songs.len() {
-            let song_metadata = SongMetadata::from(&self.songs[i]);
-            let song = EXTM3U_SONG_PATTERN
-                .replace("<duration>", &song_metadata.duration.to_string())
-                .replace("<artist>", &song_metadata.artist)
-                .replace("<title>", &song_metadata.title)
-                .replace("<path>", self.songs[i].to_str().unwrap_or(""));
+    pub fn to_extm3u(&self, options: &FormatOptions) -> String {
+        let mut content = format!("{EXTM3U_HEADER}\n");
+        let title = self.name.replace(['\r', '\n'], " ");
+        content.push_str(&format!("#PLAYLIST:{title}\n"));
+        if let (true, Some(base)) = (options.base_comment, options.relative_base()) {
+            content.push_str(&base_comment(base));
+            content.push('\n');
+        }
+
+        let metadata = self.extm3u_metadata(options);
+        let preserve = options.preserve_comments;
 
-            content.push_str(&song);
+        // songs that already had an `#EXTINF` line in the source playlist keep it
+        let mut has_extinf = false;
+        // and ones that had an `#EXTIMG` line don't get their own art
+        let mut has_image = false;
+        // other directives like `#EXTVLCOPT:` are always kept with the song after them
+        let mut directives = String::new();
+        for line in self.lines().iter() {
+            match line {
+                // the title of the source playlist is replaced by the one written above
+                SourceLine::Comment(c) if c.starts_with("#PLAYLIST:") => (),
+                SourceLine::Comment(c) => {
+                    if preserve {
+                        has_extinf |= c.starts_with("#EXTINF:");
+                        content.push_str(c);
+                        content.push('\n');
+                    } else if c.starts_with("#EXT") && !c.starts_with("#EXTINF:") {
+                        directives.push_str(c);
+                        directives.push('\n');
+                    }
+                }
+                SourceLine::Image(p) => {
+                    if let Some(p) = options.output_str(p) {
+                        has_image = true;
+                        let target = if preserve {
+                            &mut content
+                        } else {
+                            &mut directives
+                        };
+                        target.push_str(&format!("#EXTIMG:{p}\n"));
+                    }
+                }
+                SourceLine::Song(i) => {
+                    let genre = &metadata[*i].genre;
+                    if !genre.is_empty() && !directives.contains("#EXTGENRE:") {
+                        directives
+                            .push_str(&format!("#EXTGENRE:{}\n", genre.replace(['\r', '\n'], " ")));
+                    }
+                    content.push_str(&std::mem::take(&mut directives));
+                    let art = options.find_art(self.songs[*i]).filter(|_| !has_image);
+                    if let Some(art) = art.and_then(|a| options.output_str(&a)) {
+                        content.push_str(&format!("#EXTIMG:{art}\n"));
+                    }
+                    let song_metadata = Some(&metadata[*i]).filter(|_| !has_extinf);
+                    content.push_str(&self.extm3u_song(*i, song_metadata, options));
+                    has_extinf = false;
+                    has_image = false;
+                }
+                SourceLine::NotFound(p) => {
+                    if options.keep_missing {
+                        content.push_str(&missing_comment(p));
+                    } else if preserve {
+                        content.push_str(&format!("# not found: {}\n", p.display()));
+                    }
+                    directives.clear();
+                    has_extinf = false;
+                    has_image = false;
+                }
+                SourceLine::Url(url) => {
+                    content.push_str(&std::mem::take(&mut directives));
+                    if !has_extinf {
+                        content.push_str("#EXTINF:-1,\n");
+                    }
+                    content.push_str(url);
+                    content.push('\n');
+                    has_extinf = false;
+                    has_image = false;
+                }
+            }
         }
+        content.push_str(&directives);
 
         content
     }
+
+    /// The lines of the song at index `i`, with an `#EXTINF` line if `song_metadata` is passed.
+    /// Empty if the path of the song can't be written.
+    fn extm3u_song(
+        &self,
+        i: usize,
+        song_metadata: Option<&SongMetadata>,
+        options: &FormatOptions,
+    ) -> String {
+        let mut lines = String::new();
+        let Some(path) = options.output_str(self.songs[i]) else {
+            return lines;
+        };
+        if options.number_entries {
+            lines.push_str(&self.entry_number(i));
+            lines.push('\n');
+        }
+
+        let tracks = options.cue_tracks(self.songs[i]);
+        if tracks.is_empty() {
+            lines.push_str(&extm3u_entry(&path, song_metadata, options));
+        }
+        // every track of an embedded cue sheet gets its own entry
+        for t in tracks {
+            let track_metadata = song_metadata.map(|m| cue_track_metadata(m, &t));
+            let path = format!("{path}#track={}", t.number);
+            lines.push_str(&extm3u_entry(&path, track_metadata.as_ref(), options));
+        }
+
+        lines
+    }
+
+    pub fn to_pls(&self, options: &FormatOptions) -> String {
+        let mut entries = String::new();
+        let mut count = 0;
+
+        for (p, song_metadata) in self.songs.iter().zip(self.metadata(options)) {
+            let Some(path) = options.output_str(p) else {
+                continue;
+            };
+            count += 1;
+
+            let length = match song_metadata.as_secs() {
+                0 => -1,
+                d => d as i64,
+            };
+            let title = if song_metadata.artists.is_empty() {
+                song_metadata.title
+            } else {
+                format!(
+                    "{} - {}",
+                    options.artist(&song_metadata),
+                    song_metadata.title
+                )
+            };
+
+            entries.push_str(&format!(
+                "File{count}={path}\nTitle{count}={title}\nLength{count}={length}\n"
+            ));
+        }
+
+        format!("{PLS_HEADER}\nNumberOfEntries={count}\n{entries}Version=2\n")
+    }
+
+    pub fn to_xspf(&self, options: &FormatOptions) -> String {
+        let mut content = String::from(XSPF_HEADER);
+        content.push_str(&format!("  <title>{}</title>\n", xml_escape(&self.name)));
+        content.push_str("  <trackList>\n");
+
+        for (p, song_metadata) in self.songs.iter().zip(self.metadata(options)) {
+            content.push_str("    <track>\n");
+            content.push_str(&format!(
+                "      <location>{}</location>\n",
+                xml_escape(&uri_reference(&options.output_path(p)))
+            ));
+            if !song_metadata.title.is_empty() {
+                content.push_str(&format!(
+                    "      <title>{}</title>\n",
+                    xml_escape(&song_metadata.title)
+                ));
+            }
+            if !song_metadata.artists.is_empty() {
+                content.push_str(&format!(
+                    "      <creator>{}</creator>\n",
+                    xml_escape(&options.artist(&song_metadata))
+                ));
+            }
+            if let Some(art) = options.find_art(p) {
+                content.push_str(&format!(
+                    "      <image>{}</image>\n",
+                    xml_escape(&uri_reference(&options.output_path(&art)))
+                ));
+            }
+            if song_metadata.as_millis() > 0 {
+                content.push_str(&format!(
+                    "      <duration>{}</duration>\n",
+                    song_metadata.as_millis()
+                ));
+            }
+            content.push_str("    </track>\n");
+        }
+
+        content.push_str("  </trackList>\n</playlist>\n");
+        content
+    }
+
+    pub fn to_wpl(&self, options: &FormatOptions) -> String {
+        let media: Vec<String> = self
+            .songs
+            .iter()
+            .filter_map(|p| options.output_str(p))
+            .map(|p| format!("      <media src=\"{}\"/>\n", xml_escape(&p)))
+            .collect();
+
+        let mut content = String::from(WPL_HEADER);
+        content.push_str("  <head>\n");
+        content.push_str(&format!(
+            "    <meta name=\"ItemCount\" content=\"{}\"/>\n",
+            media.len()
+        ));
+        content.push_str(&format!("    <title>{}</title>\n", xml_escape(&self.name)));
+        content.push_str("  </head>\n  <body>\n    <seq>\n");
+        content.extend(media);
+        content.push_str("    </seq>\n  </body>\n</smil>\n");
+        content
+    }
+
+    /// A cue sheet with a track for every song. The index of a track is an offset inside its
+    /// `FILE`, so every song starts at zero in a file of its own, except for the tracks of an
+    /// embedded cue sheet, which share the file at their own offsets.
+    pub fn to_cue(&self, options: &FormatOptions) -> String {
+        let mut content = format!("TITLE {}\n", cue_string(&self.name));
+
+        let mut track = 0;
+        for (p, song_metadata) in self.songs.iter().zip(self.metadata(options)) {
+            let Some(path) = options.output_str(p) else {
+                continue;
+            };
+
+            content.push_str(&format!(
+                "FILE {} {}\n",
+                cue_string(&path),
+                cue_file_type(p)
+            ));
+            let tracks = options.cue_tracks(p);
+            if tracks.is_empty() {
+                track += 1;
+                content.push_str(&cue_track(track, &song_metadata, 0, options));
+            }
+            for t in tracks {
+                track += 1;
+                let track_metadata = cue_track_metadata(&song_metadata, &t);
+                content.push_str(&cue_track(track, &track_metadata, t.start, options));
+            }
+        }
+
+        content
+    }
+
+    pub fn to_json(&self, options: &FormatOptions) -> String {
+        let songs = self
+            .songs
+            .iter()
+            .zip(self.metadata(options))
+            .map(|(p, song_metadata)| {
+                let path = options.output_path(p);
+                JsonSong {
+                    path: path.to_string_lossy().into_owned(),
+                    path_valid: path.to_str().is_some(),
+                    artist: options.artist(&song_metadata),
+                    duration_secs: song_metadata.as_secs(),
+                    title: song_metadata.title,
+                    genre: song_metadata.genre,
+                }
+            })
+            .collect();
+        let playlist = JsonPlaylist {
+            name: &self.name,
+            songs,
+        };
+
+        let content = match options.json_pretty {
+            true => serde_json::to_string_pretty(&playlist),
+            false => serde_json::to_string(&playlist),
+        };
+        let mut content = content.unwrap_or_default();
+        content.push('\n');
+        content
+    }
+
+    pub fn to_csv(&self, options: &FormatOptions) -> String {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        if options.csv_header {
+            let header = options.csv_columns.iter().map(CsvColumn::name);
+            if let Err(e) = writer.write_record(header) {
+                log::error!("Couldn't write csv header because:\n{:?}", e);
+            }
+        }
+
+        let needs_metadata = options.csv_columns.iter().any(|c| *c != CsvColumn::Path);
+        let metadata = match needs_metadata {
+            true => self.metadata(options),
+            false => vec![SongMetadata::default(); self.songs.len()],
+        };
+        for (p, song_metadata) in self.songs.iter().zip(metadata) {
+            let path = options.output_path(p);
+
+            let record = options.csv_columns.iter().map(|c| match c {
+                CsvColumn::Path => path.to_string_lossy(),
+                CsvColumn::Title => Cow::Borrowed(song_metadata.title.as_str()),
+                CsvColumn::Artist => Cow::Owned(options.artist(&song_metadata)),
+                CsvColumn::Album => Cow::Borrowed(song_metadata.album.as_str()),
+                CsvColumn::Duration => Cow::Owned(song_metadata.as_secs().to_string()),
+            });
+            if let Err(e) = writer.write_record(record.map(|f| f.into_owned())) {
+                log::error!("Couldn't write csv record because:\n{:?}", e);
+            }
+        }
+
+        let bytes = writer.into_inner().unwrap_or_default();
+        String::from_utf8(bytes).unwrap_or_default()
+    }
+
+    /// A `# 0001` style comment for the song at index `i`, zero-padded to the number of songs.
+    fn entry_number(&self, i: usize) -> String {
+        let width = self.songs.len().to_string().len();
+        format!("# {:0width$}", i + 1)
+    }
+}
+
+/// Computes the path of the absolute `path` relative to the absolute `base` directory. Returns
+/// `None` if they don't share a root, for example if they're on different drives.
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+    if path_components.peek() != base_components.peek() {
+        return None;
+    }
+
+    while let (Some(p), Some(b)) = (path_components.peek(), base_components.peek()) {
+        if p != b {
+            break;
+        }
+        path_components.next();
+        base_components.next();
+    }
+
+    let mut relative: PathBuf = base_components.map(|_| Component::ParentDir).collect();
+    relative.extend(path_components);
+    Some(relative)
+}
+
+fn base_comment(base: &Path) -> String {
+    format!("# base: {}", base.display())
+}
+
+/// The `path` of an extm3u entry, after an `#EXTINF` line if `song_metadata` is passed.
+fn extm3u_entry(
+    path: &str,
+    song_metadata: Option<&SongMetadata>,
+    options: &FormatOptions,
+) -> String {
+    let mut lines = String::new();
+    if let Some(song_metadata) = song_metadata {
+        let template = options.extinf_template.as_deref();
+        let info = template
+            .unwrap_or(EXTINF_TEMPLATE)
+            .replace("<duration_hms>", &format_duration(song_metadata.as_secs()))
+            .replace("<duration>", &song_metadata.as_secs().to_string())
+            .replace("<artist>", &options.artist(song_metadata))
+            .replace("<title>", &song_metadata.title)
+            .replace("<album>", &song_metadata.album)
+            .replace("<track>", &song_metadata.track.to_string())
+            .replace("<path>", path);
+        lines.push_str(&format!("#EXTINF:{info}\n"));
+    }
+    lines.push_str(path);
+    lines.push('\n');
+
+    lines
+}
+
+/// A comment with the source path of a song that wasn't found. The space after the `#` keeps
+/// players from reading it as a directive.
+fn missing_comment(path: &Path) -> String {
+    let path = path.to_string_lossy().replace(['\r', '\n'], " ");
+    format!("# MISSING: {path}\n")
+}
+
+/// Formats `secs` as `H:MM:SS`, or `M:SS` if it's shorter than an hour.
+fn format_duration(secs: u64) -> String {
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match hours {
+        0 => format!("{minutes}:{secs:02}"),
+        _ => format!("{hours}:{minutes:02}:{secs:02}"),
+    }
+}
+
+/// The metadata of the track `t` of a cue sheet embedded in the song with `song_metadata`.
+fn cue_track_metadata(song_metadata: &SongMetadata, t: &CueTrack) -> SongMetadata {
+    SongMetadata {
+        title: match song_metadata.title.is_empty() {
+            true => format!("Track {}", t.number),
+            false => format!("{} (track {})", song_metadata.title, t.number),
+        },
+        track: t.number as u32,
+        duration_ms: t.duration * 1000,
+        ..song_metadata.clone()
+    }
+}
+
+/// A `TRACK` of a cue sheet, which starts `start` seconds into the `FILE` before it.
+fn cue_track(
+    track: usize,
+    song_metadata: &SongMetadata,
+    start: u64,
+    options: &FormatOptions,
+) -> String {
+    let mut content = format!("  TRACK {track:02} AUDIO\n");
+    if !song_metadata.title.is_empty() {
+        content.push_str(&format!("    TITLE {}\n", cue_string(&song_metadata.title)));
+    }
+    if !song_metadata.artists.is_empty() {
+        let artist = options.artist(song_metadata);
+        content.push_str(&format!("    PERFORMER {}\n", cue_string(&artist)));
+    }
+    // cue sheets count in frames of 1/75 seconds, the offsets are whole seconds
+    content.push_str(&format!(
+        "    INDEX 01 {:02}:{:02}:00\n",
+        start / 60,
+        start % 60
+    ));
+    content
+}
+
+/// A quoted cue sheet string, which can't contain double quotes.
+fn cue_string(s: &str) -> String {
+    format!("\"{}\"", s.replace(['"', '\r', '\n'], "'"))
+}
+
+/// The type of a `FILE` command, `WAVE` is also used for other lossless formats.
+fn cue_file_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(e) if e.eq_ignore_ascii_case("mp3") => "MP3",
+        Some(e) if e.eq_ignore_ascii_case("aif") || e.eq_ignore_ascii_case("aiff") => "AIFF",
+        _ => "WAVE",
+    }
+}
+
+fn xml_escape(s: &str) -> Cow<'_, str> {
+    if !s.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Converts a path into a percent-encoded URI reference. Absolute paths, including windows paths
+/// with a drive letter, become a `file://` URI, relative paths stay a relative reference.
+fn uri_reference(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let drive = matches!(path.as_bytes(), [d, b':', b'/', ..] if d.is_ascii_alphabetic());
+    let mut uri = String::new();
+    if path.starts_with('/') {
+        uri.push_str("file://");
+    } else if drive {
+        uri.push_str("file:///");
+    }
+
+    // a colon in a relative reference would be read as the end of a scheme
+    let absolute = !uri.is_empty();
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(b as char)
+            }
+            b':' if absolute => uri.push(':'),
+            _ => uri.push_str(&format!("%{b:02X}")),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Options that read the metadata of the `songs` from a cache holding it.
+    fn cached_options(songs: &[(&Path, SongMetadata)]) -> FormatOptions {
+        let cache = MetadataCache::default();
+        for (path, song_metadata) in songs {
+            cache.insert(path, song_metadata.clone());
+        }
+        FormatOptions {
+            metadata_cache: Some(Arc::new(cache)),
+            line_ending: LineEnding::Lf,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn duration_units_per_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let song = dir.path().join("Song.mp3");
+        fs::write(&song, "").unwrap();
+        let song_metadata = SongMetadata {
+            title: "Title".into(),
+            artists: vec!["Artist".into()],
+            duration_ms: 210_500,
+            ..Default::default()
+        };
+        let options = cached_options(&[(&song, song_metadata)]);
+        let playlist = Playlist::new("p".into(), vec![&song]);
+
+        let extm3u = playlist.to_extm3u(&options);
+        assert!(extm3u.contains("#EXTINF:210,Artist - Title\n"), "{extm3u}");
+        let xspf = playlist.to_xspf(&options);
+        assert!(xspf.contains("<duration>210500</duration>"), "{xspf}");
+    }
+
+    fn flattened(path: &str, options: FormatOptions) -> PathBuf {
+        let options = FormatOptions {
+            flatten: true,
+            ..options
+        };
+        options.output_path(Path::new(path)).into_owned()
+    }
+
+    #[test]
+    fn flatten_absolute_path() {
+        let path = flattened("/a/b/Song.mp3", FormatOptions::default());
+        assert_eq!(path, Path::new("Song.mp3"));
+    }
+
+    #[test]
+    fn flatten_relative_path() {
+        let options = FormatOptions {
+            relative: true,
+            base: Some(PathBuf::from("/a/c")),
+            ..Default::default()
+        };
+        let path = flattened("/a/b/Song.mp3", options);
+        assert_eq!(path, Path::new("Song.mp3"));
+        assert_eq!(
+            flattened("b/Song.mp3", FormatOptions::default()),
+            Path::new("Song.mp3")
+        );
+    }
+
+    #[test]
+    fn flatten_after_prefix() {
+        let options = FormatOptions {
+            strip_prefix: Some(PathBuf::from("/a")),
+            prepend_prefix: Some(PathBuf::from("/sdcard/Music")),
+            ..Default::default()
+        };
+        let path = flattened("/a/b/Song.mp3", options);
+        assert_eq!(path, Path::new("Song.mp3"));
+    }
+
+    #[test]
+    fn flattened_xspf_location_is_relative() {
+        let song = Path::new("/a/b/My Song.mp3");
+        let options = FormatOptions {
+            flatten: true,
+            ..Default::default()
+        };
+        let xspf = Playlist::new("p".into(), vec![song]).to_xspf(&options);
+        assert!(
+            xspf.contains("<location>My%20Song.mp3</location>"),
+            "{xspf}"
+        );
+    }
+
+    /// Writes a playlist with one song over an existing `p.m3u` file in a new directory, and
+    /// returns the directory.
+    fn write_over_existing(overwrite: Overwrite) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("p.m3u"), "old\n").unwrap();
+        let options = FormatOptions {
+            overwrite,
+            line_ending: LineEnding::Lf,
+            ..Default::default()
+        };
+        let playlist = Playlist::new("p".into(), vec![Path::new("/music/Song.mp3")]);
+        playlist
+            .write_to(dir.path(), PlaylistFormat::M3u, "m3u", &options)
+            .unwrap();
+        dir
+    }
+
+    #[test]
+    fn overwrite_always_replaces_the_playlist() {
+        let dir = write_over_existing(Overwrite::Always);
+        let contents = fs::read_to_string(dir.path().join("p.m3u")).unwrap();
+        assert_eq!(contents, "/music/Song.mp3\n");
+        assert!(!dir.path().join("p.m3u.bak").exists());
+    }
+
+    #[test]
+    fn overwrite_never_keeps_the_playlist() {
+        let dir = write_over_existing(Overwrite::Never);
+        let contents = fs::read_to_string(dir.path().join("p.m3u")).unwrap();
+        assert_eq!(contents, "old\n");
+        assert!(!dir.path().join("p.m3u.bak").exists());
+    }
+
+    #[test]
+    fn overwrite_backup_keeps_a_copy() {
+        let dir = write_over_existing(Overwrite::Backup);
+        let contents = fs::read_to_string(dir.path().join("p.m3u")).unwrap();
+        assert_eq!(contents, "/music/Song.mp3\n");
+        let backup = fs::read_to_string(dir.path().join("p.m3u.bak")).unwrap();
+        assert_eq!(backup, "old\n");
+    }
+
+    #[test]
+    fn duration_hms_omits_zero_hours() {
+        assert_eq!(format_duration(0), "0:00");
+        assert_eq!(format_duration(59), "0:59");
+        assert_eq!(format_duration(210), "3:30");
+    }
+
+    #[test]
+    fn duration_hms_with_hours() {
+        assert_eq!(format_duration(3600), "1:00:00");
+        assert_eq!(format_duration(3725), "1:02:05");
+        assert_eq!(format_duration(90_061), "25:01:01");
+    }
+
+    #[test]
+    fn duration_placeholders() {
+        let options = FormatOptions {
+            extinf_template: Some("<duration>,<duration_hms>".into()),
+            ..Default::default()
+        };
+        let song_metadata = SongMetadata {
+            duration_ms: 210_000,
+            ..Default::default()
+        };
+        let entry = extm3u_entry("Song.mp3", Some(&song_metadata), &options);
+        assert_eq!(entry, "#EXTINF:210,3:30\nSong.mp3\n");
+    }
+
+    /// Empty song files with the `names` in `dir`, and options that hold metadata for them.
+    fn fixture_songs(dir: &Path, names: &[&str]) -> (Vec<PathBuf>, FormatOptions) {
+        let songs: Vec<PathBuf> = names.iter().map(|n| dir.join(n)).collect();
+        let metadata: Vec<(&Path, SongMetadata)> = songs
+            .iter()
+            .map(|s| {
+                fs::write(s, "").unwrap();
+                let song_metadata = SongMetadata {
+                    title: s.file_stem().unwrap().to_string_lossy().into_owned(),
+                    artists: vec!["Artist".into()],
+                    duration_ms: 60_000,
+                    ..Default::default()
+                };
+                (s.as_path(), song_metadata)
+            })
+            .collect();
+        let options = cached_options(&metadata);
+        (songs, options)
+    }
+
+    #[test]
+    fn extm3u_header_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (songs, options) = fixture_songs(dir.path(), &["A.mp3", "B.mp3"]);
+        let source = vec![
+            SourceLine::Comment("#PLAYLIST:Old".into()),
+            SourceLine::Song(0),
+            SourceLine::Comment("#PLAYLIST:Other".into()),
+            SourceLine::Song(1),
+        ];
+        let playlist = Playlist::new("Name".into(), songs.iter().map(PathBuf::as_path).collect())
+            .with_source(source);
+        let options = FormatOptions {
+            preserve_comments: true,
+            ..options
+        };
+
+        let extm3u = playlist.to_extm3u(&options);
+        let lines: Vec<&str> = extm3u.lines().collect();
+        assert_eq!(lines[..2], ["#EXTM3U", "#PLAYLIST:Name"]);
+        let titles = lines.iter().filter(|l| l.starts_with("#PLAYLIST:")).count();
+        assert_eq!(titles, 1, "{extm3u}");
+        let first_extinf = lines.iter().position(|l| l.starts_with("#EXTINF:"));
+        assert!(first_extinf > Some(1), "{extm3u}");
+    }
+
+    fn numbered_songs(count: usize) -> Vec<PathBuf> {
+        (1..=count)
+            .map(|n| PathBuf::from(format!("/music/{n}.mp3")))
+            .collect()
+    }
+
+    #[test]
+    fn chunks_at_the_exact_limit() {
+        let songs = numbered_songs(3);
+        let playlist = Playlist::new("Name".into(), songs.iter().map(PathBuf::as_path).collect());
+        let chunks = playlist.into_chunks(3);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].name(), "Name");
+        assert_eq!(chunks[0].len(), 3);
+    }
+
+    #[test]
+    fn chunks_above_the_limit() {
+        let songs = numbered_songs(4);
+        let source = vec![
+            SourceLine::Song(0),
+            SourceLine::Song(1),
+            SourceLine::Song(2),
+            SourceLine::Comment("#EXTGRP:Last".into()),
+            SourceLine::Song(3),
+        ];
+        let playlist = Playlist::new("Name".into(), songs.iter().map(PathBuf::as_path).collect())
+            .with_source(source);
+        let chunks = playlist.into_chunks(3);
+
+        let names: Vec<&str> = chunks.iter().map(Playlist::name).collect();
+        assert_eq!(names, ["Name.1", "Name.2"]);
+        assert_eq!(chunks[0].songs(), &songs[..3]);
+        assert_eq!(chunks[1].songs(), &songs[3..]);
+        let m3u = chunks[1].to_extm3u(&FormatOptions::default());
+        assert!(m3u.contains("#EXTGRP:Last\n"), "{m3u}");
+    }
+
+    #[test]
+    fn crlf_in_every_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let (songs, options) = fixture_songs(dir.path(), &["A.mp3", "B.flac"]);
+        let options = FormatOptions {
+            line_ending: LineEnding::Crlf,
+            csv_header: true,
+            json_pretty: true,
+            number_entries: true,
+            preserve_comments: true,
+            ..options
+        };
+        let source = vec![
+            SourceLine::Comment("#EXTGRP:Group".into()),
+            SourceLine::Song(0),
+            SourceLine::NotFound(PathBuf::from("Missing.mp3")),
+            SourceLine::Song(1),
+        ];
+        let playlist = Playlist::new("Name".into(), songs.iter().map(PathBuf::as_path).collect())
+            .with_source(source);
+
+        for format in PlaylistFormat::ALL {
+            let content = playlist.format(format, &options);
+            assert!(content.contains("\r\n"), "{format:?}: {content:?}");
+            assert!(
+                !content.replace("\r\n", "").contains(['\r', '\n']),
+                "{format:?}: {content:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn extm3u_framing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (songs, options) = fixture_songs(dir.path(), &["A.mp3", "B.mp3"]);
+        for count in 0..=2 {
+            let songs = songs[..count].iter().map(PathBuf::as_path).collect();
+            let extm3u = Playlist::new("Name".into(), songs).to_extm3u(&options);
+            assert!(extm3u.starts_with("#EXTM3U\n"), "{extm3u:?}");
+            assert!(
+                extm3u.ends_with('\n') && !extm3u.ends_with("\n\n"),
+                "{extm3u:?}"
+            );
+            let extinfs = extm3u.matches("#EXTINF:").count();
+            assert_eq!(extinfs, count, "{extm3u:?}");
+        }
+    }
+
+    #[test]
+    fn xspf_golden() {
+        let dir = tempfile::tempdir().unwrap();
+        let album = dir.path().join("Rock & Roll");
+        fs::create_dir(&album).unwrap();
+        let (songs, options) = fixture_songs(&album, &["Tom's Song #1.mp3", "Ä:b.mp3"]);
+        let options = FormatOptions {
+            relative: true,
+            base: Some(dir.path().join("playlists")),
+            ..options
+        };
+        options.metadata_cache.as_ref().unwrap().insert(
+            &songs[0],
+            SongMetadata {
+                title: "Tom's \"Song\"".into(),
+                artists: vec!["<A> & B".into()],
+                duration_ms: 1500,
+                ..Default::default()
+            },
+        );
+        let playlist = Playlist::new("A & B".into(), songs.iter().map(PathBuf::as_path).collect());
+
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <title>A &amp; B</title>
+  <trackList>
+    <track>
+      <location>../Rock%20%26%20Roll/Tom%27s%20Song%20%231.mp3</location>
+      <title>Tom&apos;s &quot;Song&quot;</title>
+      <creator>&lt;A&gt; &amp; B</creator>
+      <duration>1500</duration>
+    </track>
+    <track>
+      <location>../Rock%20%26%20Roll/%C3%84%3Ab.mp3</location>
+      <title>Ä:b</title>
+      <creator>Artist</creator>
+      <duration>60000</duration>
+    </track>
+  </trackList>
+</playlist>
+"#;
+        assert_eq!(playlist.to_xspf(&options), expected);
+    }
+
+    #[test]
+    fn write_to_creates_nested_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out/nested");
+        let playlist = Playlist::new("p".into(), vec![Path::new("/music/Song.mp3")])
+            .with_dir(PathBuf::from("Rock/Live"));
+        let options = FormatOptions {
+            line_ending: LineEnding::Lf,
+            ..Default::default()
+        };
+        playlist
+            .write_to(&output, PlaylistFormat::M3u, "m3u", &options)
+            .unwrap();
+
+        let contents = fs::read_to_string(output.join("Rock/Live/p.m3u")).unwrap();
+        assert_eq!(contents, "/music/Song.mp3\n");
+    }
+
+    fn extinf_with(template: &str) -> String {
+        let options = FormatOptions {
+            extinf_template: Some(template.into()),
+            ..Default::default()
+        };
+        let song_metadata = SongMetadata {
+            title: "Title".into(),
+            artists: vec!["A".into(), "B".into()],
+            album: "Album".into(),
+            track: 7,
+            duration_ms: 187_900,
+            ..Default::default()
+        };
+        let entry = extm3u_entry("Dir/Song.mp3", Some(&song_metadata), &options);
+        entry.lines().next().unwrap().to_string()
+    }
+
+    #[test]
+    fn extinf_template_tokens() {
+        assert_eq!(extinf_with("<duration>"), "#EXTINF:187");
+        assert_eq!(extinf_with("<artist>"), "#EXTINF:A; B");
+        assert_eq!(extinf_with("<title>"), "#EXTINF:Title");
+        assert_eq!(extinf_with("<album>"), "#EXTINF:Album");
+        assert_eq!(extinf_with("<track>"), "#EXTINF:7");
+        assert_eq!(extinf_with("<path>"), "#EXTINF:Dir/Song.mp3");
+        assert_eq!(
+            extinf_with("<duration>,<track>. <title> (<artist>)"),
+            "#EXTINF:187,7. Title (A; B)"
+        );
+    }
+
+    #[test]
+    fn extinf_template_default() {
+        assert_eq!(extinf_with(EXTINF_TEMPLATE), "#EXTINF:187,A; B - Title");
+    }
+
+    #[test]
+    fn extinf_template_unknown_tokens_are_literal() {
+        assert_eq!(
+            extinf_with("<duration>,<year> <title> <>"),
+            "#EXTINF:187,<year> Title <>"
+        );
+    }
+
+    fn strip_options(prefix: &str) -> FormatOptions {
+        FormatOptions {
+            strip_prefix: Some(PathBuf::from(prefix)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn strip_matching_prefix() {
+        let options = strip_options("/mnt/music");
+        let path = options.output_path(Path::new("/mnt/music/Artist/Song.mp3"));
+        assert_eq!(path, Path::new("Artist/Song.mp3"));
+    }
+
+    #[test]
+    fn strip_non_matching_prefix() {
+        let options = strip_options("/mnt/music");
+        let path = options.output_path(Path::new("/home/me/Artist/Song.mp3"));
+        assert_eq!(path, Path::new("/home/me/Artist/Song.mp3"));
+        // prefixes are compared by whole components
+        let path = options.output_path(Path::new("/mnt/musicians/Song.mp3"));
+        assert_eq!(path, Path::new("/mnt/musicians/Song.mp3"));
+    }
+
+    #[test]
+    fn strip_and_prepend_prefix_round_trip() {
+        let options = FormatOptions {
+            prepend_prefix: Some(PathBuf::from("/storage/emulated/0/Music")),
+            ..strip_options("/mnt/music")
+        };
+        let song = Path::new("/mnt/music/Artist/Song.mp3");
+        let written = options.output_path(song);
+        assert_eq!(
+            written,
+            Path::new("/storage/emulated/0/Music/Artist/Song.mp3")
+        );
+        let candidates = options.local_candidates(&written, Path::new("/playlists"));
+        assert!(candidates.iter().any(|c| c == song), "{candidates:?}");
+    }
+
+    #[test]
+    fn prepend_prefix_after_relative() {
+        let options = FormatOptions {
+            relative: true,
+            base: Some(PathBuf::from("/mnt/music")),
+            prepend_prefix: Some(PathBuf::from("/sdcard")),
+            ..Default::default()
+        };
+        let written = options.output_path(Path::new("/mnt/music/Artist/Song.mp3"));
+        assert_eq!(written, Path::new("/sdcard/Artist/Song.mp3"));
+    }
+
+    #[test]
+    fn wpl_media_per_song() {
+        let songs = [
+            Path::new("/music/Rock & Roll/Song.mp3"),
+            Path::new("/music/\"Quoted\".mp3"),
+            Path::new("/music/<Tag>.flac"),
+        ];
+        let playlist = Playlist::new("A & B".into(), songs.to_vec());
+        let wpl = playlist.to_wpl(&FormatOptions::default());
+
+        assert!(
+            wpl.starts_with("<?wpl version=\"1.0\"?>\n<smil>\n"),
+            "{wpl}"
+        );
+        assert!(wpl.ends_with("</smil>\n"), "{wpl}");
+        assert!(
+            wpl.contains("<meta name=\"ItemCount\" content=\"3\"/>"),
+            "{wpl}"
+        );
+        assert!(wpl.contains("<title>A &amp; B</title>"), "{wpl}");
+        assert_eq!(wpl.matches("<media ").count(), songs.len(), "{wpl}");
+
+        let entries = crate::parse_playlist_as(&wpl, PlaylistInputFormat::Wpl);
+        let paths: Vec<&Path> = entries.iter().map(|e| e.path.as_path()).collect();
+        assert_eq!(paths, songs);
+    }
+
+    #[test]
+    fn write_to_fails_if_a_file_blocks_the_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Rock"), "").unwrap();
+        let playlist = Playlist::new("p".into(), vec![Path::new("/music/Song.mp3")])
+            .with_dir(PathBuf::from("Rock"));
+        let result = playlist.write_to(
+            dir.path(),
+            PlaylistFormat::M3u,
+            "m3u",
+            &FormatOptions::default(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn dedup_drops_the_extinf_of_duplicates() {
+        let (a, other) = (Path::new("/music/A.mp3"), Path::new("/music/Other.mp3"));
+        let source = vec![
+            SourceLine::Comment("#EXTINF:100,X - A".into()),
+            SourceLine::Song(0),
+            SourceLine::Comment("#EXTGRP:Group".into()),
+            SourceLine::Comment("#EXTINF:200,Y - Dup".into()),
+            SourceLine::Image(PathBuf::from("/music/dup.jpg")),
+            SourceLine::Song(1),
+            SourceLine::Comment("#EXTINF:300,Z - Other".into()),
+            SourceLine::Song(2),
+        ];
+        let mut playlist = Playlist::new("p".into(), vec![a, a, other]).with_source(source);
+        assert_eq!(playlist.dedup(), 1);
+
+        let options = FormatOptions {
+            preserve_comments: true,
+            line_ending: LineEnding::Lf,
+            ..Default::default()
+        };
+        let extm3u = playlist.to_extm3u(&options);
+        let expected = "#EXTM3U\n#PLAYLIST:p\n\
+            #EXTINF:100,X - A\n/music/A.mp3\n\
+            #EXTGRP:Group\n#EXTINF:300,Z - Other\n/music/Other.mp3\n";
+        assert_eq!(extm3u, expected);
+    }
+
+    #[test]
+    fn index_names_with_a_dot_keep_their_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let playlists = [Playlist::new("Chill".into(), Vec::new())];
+        let format = PlaylistFormat::Extm3u;
+        write_index(
+            dir.path(),
+            "Mix v1.2",
+            &playlists,
+            format,
+            "m3u",
+            LineEnding::Lf,
+        )
+        .unwrap();
+        let index = fs::read_to_string(dir.path().join("Mix v1.2.m3u")).unwrap();
+        assert_eq!(index, "#EXTM3U\n#EXTINF:-1,Chill\nChill.m3u\n");
+    }
 }
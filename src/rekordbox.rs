@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use crate::itunes::file_url_path;
+use crate::{platform_path, xml_attribute, xml_unescape, Entry, ExtInf};
+
+/// The separator between the names of the folders of a playlist and its own name.
+pub const FOLDER_SEPARATOR: &str = " - ";
+
+/// Reads the names and song paths of all playlists of a Rekordbox `rekordbox.xml` export at
+/// `path`. Playlists inside folders are named after the folders and the playlist, joined by
+/// [`FOLDER_SEPARATOR`], and tracks without a local file are skipped.
+pub fn rekordbox_playlists(path: &Path) -> io::Result<Vec<(String, Vec<Entry>)>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(parse_rekordbox(&contents))
+}
+
+/// An open `<NODE>`, which is either a folder or a playlist.
+struct Node {
+    name: String,
+    /// The tracks of a playlist, `None` for folders.
+    entries: Option<Vec<Entry>>,
+    /// Whether the tracks of the playlist are referenced by their location instead of their id.
+    by_location: bool,
+}
+
+fn parse_rekordbox(contents: &str) -> Vec<(String, Vec<Entry>)> {
+    let mut tracks_by_id: HashMap<String, Entry> = HashMap::new();
+    let mut tracks_by_location: HashMap<String, Entry> = HashMap::new();
+    let mut in_collection = false;
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut playlists = Vec::new();
+
+    let mut rest = contents;
+    while let Some(start) = rest.find('<') {
+        let Some(len) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + len];
+        rest = &rest[start + len + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            match name.trim() {
+                "COLLECTION" => in_collection = false,
+                "NODE" => {
+                    if let Some(node) = nodes.pop() {
+                        finish_node(node, &nodes, &mut playlists);
+                    }
+                }
+                _ => (),
+            }
+            continue;
+        }
+
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        let attribute = |name| xml_attribute(attributes, name).map(xml_unescape);
+        match name {
+            "COLLECTION" => in_collection = !self_closing,
+            "TRACK" if in_collection => {
+                let (Some(id), Some(location)) = (attribute("TrackID"), attribute("Location"))
+                else {
+                    continue;
+                };
+                let Some(entry) = track_entry(&location, attributes) else {
+                    continue;
+                };
+                tracks_by_id.insert(id, entry.clone());
+                tracks_by_location.insert(location, entry);
+            }
+            "TRACK" => {
+                let Some(node) = nodes.last_mut() else {
+                    continue;
+                };
+                let Some(entries) = node.entries.as_mut() else {
+                    continue;
+                };
+                let tracks = match node.by_location {
+                    true => &tracks_by_location,
+                    false => &tracks_by_id,
+                };
+                if let Some(entry) = attribute("Key").and_then(|k| tracks.get(&k)) {
+                    entries.push(entry.clone());
+                }
+            }
+            "NODE" => {
+                // type 0 is a folder, and 1 a playlist
+                let is_playlist = attribute("Type").as_deref() == Some("1");
+                let node = Node {
+                    name: attribute("Name").unwrap_or_default(),
+                    entries: is_playlist.then(Vec::new),
+                    by_location: attribute("KeyType").as_deref() == Some("1"),
+                };
+                match self_closing {
+                    true => finish_node(node, &nodes, &mut playlists),
+                    false => nodes.push(node),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    playlists
+}
+
+/// Adds the `node` to the `playlists` if it is one, named after the open folder `nodes` around
+/// it. The outermost node is the root of all playlists and isn't part of the name.
+fn finish_node(node: Node, nodes: &[Node], playlists: &mut Vec<(String, Vec<Entry>)>) {
+    let Some(entries) = node.entries else {
+        return;
+    };
+    let mut names: Vec<&str> = nodes.iter().skip(1).map(|n| n.name.as_str()).collect();
+    names.push(&node.name);
+    playlists.push((names.join(FOLDER_SEPARATOR), entries));
+}
+
+fn track_entry(location: &str, attributes: &str) -> Option<Entry> {
+    let path = file_url_path(location)?;
+    let attribute = |name| xml_attribute(attributes, name).map(xml_unescape);
+    let extinf = ExtInf {
+        duration: attribute("TotalTime").and_then(|t| t.parse().ok()),
+        artist: attribute("Artist").filter(|a| !a.is_empty()),
+        title: attribute("Name").filter(|n| !n.is_empty()),
+    };
+
+    Some(Entry {
+        path: platform_path(&path),
+        extinf: Some(extinf),
+        comments: Vec::new(),
+    })
+}
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use playlist_localizer::{EntryMatch, MusicIndex};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Report<'a> {
+    totals: Totals,
+    playlists: Vec<PlaylistReport<'a>>,
+}
+
+#[derive(Default, Serialize)]
+struct Totals {
+    playlists: usize,
+    songs: usize,
+    matched: usize,
+    unmatched: usize,
+}
+
+#[derive(Serialize)]
+struct PlaylistReport<'a> {
+    name: &'a str,
+    songs: Vec<SongReport>,
+}
+
+#[derive(Serialize)]
+struct SongReport {
+    source: String,
+    resolved: Option<String>,
+    matched: bool,
+    candidates: usize,
+    confidence: Option<u8>,
+    fallback: bool,
+}
+
+/// Writes a json report of how the entries of each playlist were matched to `path`.
+pub fn write_report(path: &Path, playlists: &[(String, Vec<EntryMatch>)]) -> io::Result<()> {
+    let mut totals = Totals {
+        playlists: playlists.len(),
+        ..Default::default()
+    };
+    let playlists = playlists
+        .iter()
+        .map(|(name, entries)| {
+            let songs: Vec<SongReport> = entries
+                .iter()
+                .map(|e| SongReport {
+                    source: e.source.to_string_lossy().into_owned(),
+                    resolved: e.resolved.map(|p| p.to_string_lossy().into_owned()),
+                    matched: e.resolved.is_some(),
+                    candidates: e.candidates,
+                    confidence: e.confidence,
+                    fallback: e.fallback,
+                })
+                .collect();
+            totals.songs += songs.len();
+            totals.matched += songs.iter().filter(|s| s.matched).count();
+            PlaylistReport { name, songs }
+        })
+        .collect();
+    totals.unmatched = totals.songs - totals.matched;
+
+    let report = Report { totals, playlists };
+    let mut content = serde_json::to_string_pretty(&report)?;
+    content.push('\n');
+    fs::write(path, content)
+}
+
+/// Writes the songs of the `index` by their key as json to `path`, sorted by the key.
+pub fn write_index_dump(path: &Path, index: &MusicIndex) -> io::Result<()> {
+    let songs: BTreeMap<String, Vec<String>> = index
+        .songs
+        .iter()
+        .map(|(key, songs)| {
+            let songs = songs.iter().map(|s| s.to_string_lossy().into_owned());
+            (key.to_string_lossy().into_owned(), songs.collect())
+        })
+        .collect();
+    let mut content = serde_json::to_string_pretty(&songs)?;
+    content.push('\n');
+    fs::write(path, content)
+}
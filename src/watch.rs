@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use playlist_localizer::canonicalize;
+
+use crate::error::AppError;
+
+/// How long to wait for more changes after a playlist changed, players often write a playlist in
+/// several steps.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Runs `localize`, and again whenever a file with one of the playlist `extensions` inside the
+/// `dirs` is created, changed or removed, until interrupted with ctrl-c. Changes inside the
+/// `output_dir` are ignored, so the written playlists don't trigger another run.
+pub fn watch(
+    dirs: &[PathBuf],
+    extensions: &[String],
+    output_dir: Option<&Path>,
+    mut localize: impl FnMut() -> Result<(), AppError>,
+) -> Result<(), AppError> {
+    // `None` is sent when the process is interrupted
+    let (sender, receiver) = mpsc::channel();
+    let interrupt = sender.clone();
+    ctrlc::set_handler(move || {
+        let _ = interrupt.send(None);
+    })
+    .map_err(AppError::Interrupt)?;
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(Some(event));
+    })
+    .map_err(AppError::Watch)?;
+    for dir in dirs {
+        watcher
+            .watch(dir, RecursiveMode::Recursive)
+            .map_err(AppError::Watch)?;
+    }
+
+    loop {
+        // a failed run is reported, but the playlists are localized again on the next change
+        if let Err(e) = localize() {
+            error!("{e}");
+        }
+        info!("watching for playlist changes, press ctrl-c to exit...");
+
+        loop {
+            match receiver.recv() {
+                Ok(Some(Ok(event))) if is_playlist_change(&event, extensions, output_dir) => {
+                    debug!("{:?} {:?}", event.kind, event.paths);
+                    break;
+                }
+                Ok(Some(Ok(_))) => (),
+                Ok(Some(Err(e))) => warn!("{e}"),
+                Ok(None) | Err(_) => return Ok(()),
+            }
+        }
+        loop {
+            match receiver.recv_timeout(DEBOUNCE) {
+                Ok(Some(_)) => (),
+                Ok(None) => return Ok(()),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// The absolute path of the output dir, as it's compared with the changed paths. The output dir
+/// is only canonicalized if it exists, it's created by the first run otherwise.
+pub fn output_dir_path(output_dir: &Path) -> PathBuf {
+    canonicalize(output_dir)
+        .or_else(|_| std::path::absolute(output_dir))
+        .unwrap_or_else(|_| output_dir.to_path_buf())
+}
+
+/// Whether the `event` created, changed or removed a playlist outside of the `output_dir`.
+fn is_playlist_change(
+    event: &notify::Event,
+    extensions: &[String],
+    output_dir: Option<&Path>,
+) -> bool {
+    let changed = matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    );
+    changed
+        && event.paths.iter().any(|p| {
+            let is_playlist = p
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| extensions.iter().any(|x| x.eq_ignore_ascii_case(e)));
+            let absolute = std::path::absolute(p);
+            let in_output =
+                output_dir.is_some_and(|o| absolute.as_deref().unwrap_or(p).starts_with(o));
+            is_playlist && !in_output
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use notify::event::CreateKind;
+
+    use super::*;
+
+    fn created(path: PathBuf) -> notify::Event {
+        notify::Event::new(EventKind::Create(CreateKind::File)).add_path(path)
+    }
+
+    #[test]
+    fn missing_relative_output_dir_is_ignored() {
+        let extensions = ["m3u".to_string()];
+        let output_dir = output_dir_path(Path::new("music/missing-out"));
+        assert!(output_dir.is_absolute());
+
+        let cwd = std::env::current_dir().unwrap();
+        let written = created(cwd.join("music/missing-out/p.m3u"));
+        assert!(!is_playlist_change(
+            &written,
+            &extensions,
+            Some(&output_dir)
+        ));
+        let written = created(PathBuf::from("music/missing-out/p.m3u"));
+        assert!(!is_playlist_change(
+            &written,
+            &extensions,
+            Some(&output_dir)
+        ));
+
+        let source = created(cwd.join("music/p.m3u"));
+        assert!(is_playlist_change(&source, &extensions, Some(&output_dir)));
+        let song = created(cwd.join("music/Song.mp3"));
+        assert!(!is_playlist_change(&song, &extensions, Some(&output_dir)));
+    }
+}
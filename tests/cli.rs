@@ -0,0 +1,177 @@
+//! Runs the binary on music files and playlists in a temporary directory.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Creates the empty `files` inside `dir`, along with their parent directories.
+fn create_files(dir: &Path, files: &[&str]) {
+    for f in files {
+        let path = dir.join(f);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+}
+
+/// Runs the binary in `dir` with an empty config file, so the one of the user isn't read.
+fn run(dir: &Path, args: &[&str]) -> Output {
+    let config = dir.join("config.toml");
+    fs::write(&config, "").unwrap();
+    Command::new(env!("CARGO_BIN_EXE_playlist-localizer"))
+        .arg("--config")
+        .arg(&config)
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+/// The sorted file names inside `dir`.
+fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn playlist_glob_only_writes_matching_playlists() {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(dir.path(), &["music/A/Song.mp3"]);
+    fs::create_dir(dir.path().join("lists")).unwrap();
+    for name in ["Workout 1.m3u", "Workout2.m3u", "Chill.m3u"] {
+        fs::write(dir.path().join("lists").join(name), "A/Song.mp3\n").unwrap();
+    }
+    let localize = |output_dir: &str, globs: &[&str]| {
+        let mut args = vec!["-m", "music", "--playlist-dir", "lists", "-o", output_dir];
+        for g in globs {
+            args.extend(["--playlist-glob", g]);
+        }
+        let output = run(dir.path(), &args);
+        assert!(output.status.success(), "{output:?}");
+        file_names(&dir.path().join(output_dir))
+    };
+
+    assert_eq!(
+        localize("out", &["Workout*.m3u"]),
+        ["Workout 1.m3u", "Workout2.m3u"]
+    );
+    assert_eq!(
+        localize("out2", &["Chill*", "*2.m3u"]),
+        ["Chill.m3u", "Workout2.m3u"]
+    );
+}
+
+#[test]
+fn quiet_prints_nothing_on_success() {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(dir.path(), &["music/A/Song.mp3"]);
+    fs::write(dir.path().join("music/p.m3u"), "A/Song.mp3\nMissing.mp3\n").unwrap();
+
+    let output = run(dir.path(), &["-m", "music", "-o", "out", "-q"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    let output = run(dir.path(), &["-m", "music", "-o", "out2"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("indexing..."));
+}
+
+#[test]
+fn failed_writes_are_counted() {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(dir.path(), &["music/A/Song.mp3"]);
+    for name in ["Blocked.m3u", "Written.m3u"] {
+        fs::write(dir.path().join("music").join(name), "A/Song.mp3\n").unwrap();
+    }
+    // a directory where the playlist would be written
+    fs::create_dir_all(dir.path().join("out/Blocked.m3u")).unwrap();
+
+    let output = run(dir.path(), &["-m", "music", "-o", "out", "-y"]);
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Couldn't write"), "{stderr}");
+    assert!(
+        stderr.contains("1 playlists couldn't be written"),
+        "{stderr}"
+    );
+    let written = fs::read_to_string(dir.path().join("out/Written.m3u")).unwrap();
+    assert!(written.ends_with("Song.mp3\n"), "{written}");
+}
+
+#[test]
+fn report_marks_fallback_matches() {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(dir.path(), &["music/A/Local.mp3", "backup/A/Backup.mp3"]);
+    fs::create_dir(dir.path().join("lists")).unwrap();
+    let playlist = "A/Local.mp3\nA/Backup.mp3\n";
+    fs::write(dir.path().join("lists/p.m3u"), playlist).unwrap();
+
+    let args = [
+        "-m",
+        "music",
+        "--playlist-dir",
+        "lists",
+        "-o",
+        "out",
+        "--fallback-dir",
+        "backup",
+        "--report",
+        "report.json",
+    ];
+    let output = run(dir.path(), &args);
+    assert!(output.status.success(), "{output:?}");
+    let report = fs::read_to_string(dir.path().join("report.json")).unwrap();
+    let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+    let songs = report["playlists"][0]["songs"].as_array().unwrap();
+    let fallback: Vec<_> = songs.iter().map(|s| s["fallback"].as_bool()).collect();
+    assert_eq!(fallback, [Some(false), Some(true)]);
+    assert!(songs.iter().all(|s| s["confidence"].is_u64()), "{songs:?}");
+}
+
+#[test]
+fn playlists_with_the_same_output_path_are_written_once() {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(dir.path(), &["music/A/Song.mp3", "music/B/Other.mp3"]);
+    fs::create_dir_all(dir.path().join("lists/a")).unwrap();
+    fs::create_dir_all(dir.path().join("lists/b")).unwrap();
+    fs::write(dir.path().join("lists/a/x.m3u"), "A/Song.mp3\n").unwrap();
+    fs::write(dir.path().join("lists/b/x.m3u"), "B/Other.mp3\n").unwrap();
+
+    let output = run(
+        dir.path(),
+        &["-m", "music", "--playlist-dir", "lists", "-o", "out"],
+    );
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("would both be written to"), "{stderr}");
+    assert_eq!(file_names(&dir.path().join("out")), ["x.m3u"]);
+}
+
+#[test]
+fn strict_fails_instead_of_prompting_without_a_terminal() {
+    let dir = tempfile::tempdir().unwrap();
+    create_files(dir.path(), &["music/A/Song.mp3", "out/p.m3u"]);
+    fs::write(dir.path().join("music/p.m3u"), "A/Song.mp3\n").unwrap();
+
+    let args = ["-m", "music", "-o", "out", "--confirm-threshold", "0"];
+    let output = run(dir.path(), &[&args[..], &["--strict"]].concat());
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("would be overwritten"), "{stderr}");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/p.m3u")).unwrap(),
+        ""
+    );
+
+    // without --strict the files are overwritten without asking
+    let output = run(dir.path(), &args);
+    assert!(output.status.success(), "{output:?}");
+    assert_ne!(
+        fs::read_to_string(dir.path().join("out/p.m3u")).unwrap(),
+        ""
+    );
+}